@@ -0,0 +1,53 @@
+// Evaluations per second of the ConnectN heuristic on a few board sizes.
+// Run with `cargo bench --bench evaluator`.
+use std::time::{Duration, Instant};
+
+use tictactoe::connectn::ConnectN;
+use tictactoe::evaluator::Evaluator;
+use tictactoe::game::{Game, GameStatus};
+use tictactoe::lookahead::Heuristic;
+use tictactoe::rng::Rng;
+
+// A position roughly a third full, reached by random moves.
+fn random_position(columns: usize, rows: usize, win_length: usize, rng: &mut Rng) -> ConnectN {
+    loop {
+        let mut game = ConnectN::new(columns, rows, win_length);
+        let mut status = GameStatus::InProgress;
+        for _ in 0..columns * rows / 3 {
+            let mut moves = game.legal_moves();
+            let input = moves.swap_remove(rng.below(moves.len()));
+            let player = game.current_player();
+            status = game.play(player, &input).unwrap();
+            if status != GameStatus::InProgress {
+                break
+            }
+        }
+        if status == GameStatus::InProgress {
+            return game
+        }
+    }
+}
+
+fn main() {
+    let mut rng = Rng::new(1);
+    for &(columns, rows, win_length) in &[(7, 6, 4), (9, 7, 5), (15, 15, 5)] {
+        let positions: Vec<ConnectN> = (0..100)
+            .map(|_| random_position(columns, rows, win_length, &mut rng))
+            .collect();
+        let evaluator = Evaluator::new(win_length);
+
+        let start = Instant::now();
+        let mut evaluations = 0u64;
+        let mut checksum = 0i64;
+        while start.elapsed() < Duration::from_secs(2) {
+            for position in &positions {
+                let player = position.current_player();
+                checksum += evaluator.evaluate(position, player) as i64;
+                evaluations += 1;
+            }
+        }
+        let rate = evaluations as f64 / start.elapsed().as_secs_f64();
+        println!("{}x{} connect {}: {:.0} evaluations/s (checksum {})",
+            columns, rows, win_length, rate, checksum);
+    }
+}
@@ -0,0 +1,49 @@
+// Time to solve a fixed set of connect four positions on 1, 2, 4 and 8
+// threads, and the speedup over one thread. Each run starts with an empty
+// transposition table. Run with `cargo bench --bench solver`.
+use std::time::Instant;
+
+use tictactoe::agent::Evaluation;
+use tictactoe::board::Player;
+use tictactoe::connectn::ConnectN;
+use tictactoe::game::Game;
+use tictactoe::solver::Solver;
+
+const POSITIONS: [&str; 4] = [
+    "52461125423375",
+    "2256177761131247",
+    "4453432244",
+    "3344552211",
+];
+
+fn game_from_moves(moves: &str) -> (ConnectN, Player) {
+    let mut game = ConnectN::new(7, 6, 4);
+    for column in moves.chars() {
+        let player = game.current_player();
+        game.play(player, &[column.to_digit(10).unwrap() as usize - 1]).unwrap();
+    }
+    let player = game.current_player();
+    (game, player)
+}
+
+fn main() {
+    let positions: Vec<(ConnectN, Player)> = POSITIONS.iter().map(|moves| game_from_moves(moves)).collect();
+    let mut baseline = None;
+    let mut expected: Vec<Evaluation> = Vec::new();
+    for threads in [1, 2, 4, 8] {
+        let start = Instant::now();
+        let mut results = Vec::new();
+        for (game, player) in &positions {
+            let mut solver = Solver::for_game(game).with_threads(threads);
+            results.push(solver.solve(game, *player));
+        }
+        let elapsed = start.elapsed();
+        if expected.is_empty() {
+            expected = results.clone();
+        }
+        assert_eq!(results, expected, "{} threads disagree with one", threads);
+
+        let baseline = *baseline.get_or_insert(elapsed);
+        println!("{} threads: {:.2?} ({:.2}x)", threads, elapsed, baseline.as_secs_f64() / elapsed.as_secs_f64());
+    }
+}
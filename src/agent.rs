@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::Duration;
+
+use crate::board::Player;
+use crate::game::{Game, GameStatus};
+
+// The value of a position for the side to move. Distances count plies until
+// the game ends, including the final move. Engines that can't see to the end
+// report a heuristic score instead, positive when the side to move is ahead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Evaluation {
+    Win(usize),
+    Draw,
+    Loss(usize),
+    Heuristic(i32),
+}
+
+impl Evaluation {
+    // What a move is worth if it leads to a position worth `self` to the
+    // opponent.
+    pub fn backed_up(self) -> Evaluation {
+        match self {
+            Evaluation::Win(distance) => Evaluation::Loss(distance + 1),
+            Evaluation::Draw => Evaluation::Draw,
+            Evaluation::Loss(distance) => Evaluation::Win(distance + 1),
+            Evaluation::Heuristic(score) => Evaluation::Heuristic(-score),
+        }
+    }
+
+    // A short form for labelling moves: W3, D, L4 or a signed score.
+    pub fn label(self) -> String {
+        match self {
+            Evaluation::Win(distance) => format!("W{}", distance),
+            Evaluation::Draw => "D".to_string(),
+            Evaluation::Loss(distance) => format!("L{}", distance),
+            Evaluation::Heuristic(score) => format!("{:+}", score),
+        }
+    }
+
+    // Quicker wins beat slower ones and slower losses beat quicker ones. A
+    // proven draw goes just above an even score, so that only equal
+    // evaluations rank the same.
+    fn rank(self) -> (i32, i64, i32) {
+        match self {
+            Evaluation::Win(distance) => (1, -(distance as i64), 0),
+            Evaluation::Draw => (0, 0, 1),
+            Evaluation::Heuristic(score) => (0, score as i64, 0),
+            Evaluation::Loss(distance) => (-1, distance as i64, 0),
+        }
+    }
+}
+
+impl Ord for Evaluation {
+    fn cmp(&self, other: &Evaluation) -> Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for Evaluation {
+    fn partial_cmp(&self, other: &Evaluation) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Evaluation::Win(distance) => write!(f, "wins in {}", distance),
+            Evaluation::Draw => write!(f, "draws"),
+            Evaluation::Loss(distance) => write!(f, "loses in {}", distance),
+            Evaluation::Heuristic(score) => write!(f, "scores {}", score),
+        }
+    }
+}
+
+// A computer player. Given the current position and the side to move, it
+// returns an input in the same form a human would type it in.
+pub trait Agent<G: Game> {
+    fn choose_move(&mut self, game: &G, player: Player) -> Vec<usize>;
+
+    // What went into the last move, for engines that search to a depth.
+    fn last_search(&self) -> Option<SearchInfo> {
+        None
+    }
+}
+
+// The engines only know X against O, with X to start, and check that's what
+// they've been given rather than trust every caller to.
+pub fn assert_two_player<G: Game>(game: &G) {
+    assert!(game.players().is_two_player(), "Computer players only play X against O");
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchInfo {
+    pub depth: usize,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    // The line of play the engine expects, starting with its own move. It
+    // may stop short of the depth searched.
+    pub principal_variation: Vec<Vec<usize>>,
+    // Transposition table lookups, and how many of them found the position.
+    pub table_probes: u64,
+    pub table_hits: u64,
+}
+
+impl SearchInfo {
+    // None for engines without a transposition table.
+    pub fn hit_rate(&self) -> Option<f64> {
+        if self.table_probes == 0 {
+            None
+        } else {
+            Some(self.table_hits as f64 / self.table_probes as f64)
+        }
+    }
+}
+
+impl fmt::Display for SearchInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "depth {}, {} nodes in {:.0?}", self.depth, self.nodes, self.elapsed)
+    }
+}
+
+// Suggests a move for the side to move, with what it's worth. Returns None
+// once the game is over.
+pub trait Advisor<G: Game> {
+    fn advise(&mut self, game: &G, player: Player) -> Option<(Vec<usize>, Evaluation)>;
+
+    // Every legal move with what it's worth. By default each move's value is
+    // backed up from advice on the position it leads to.
+    fn analyze(&mut self, game: &G, player: Player) -> Vec<(Vec<usize>, Evaluation)> {
+        let mut analysis = Vec::new();
+        for input in game.legal_moves() {
+            let mut child = game.clone();
+            let evaluation = match child.play(player, &input) {
+                Ok(GameStatus::Won(..) | GameStatus::Connected(..)) => Evaluation::Win(1),
+                Ok(GameStatus::Drawn) => Evaluation::Draw,
+                Ok(GameStatus::InProgress) => match self.advise(&child, player.opponent()) {
+                    Some((_, evaluation)) => evaluation.backed_up(),
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            analysis.push((input, evaluation));
+        }
+        analysis
+    }
+}
+
+#[test]
+fn evaluations_only_rank_the_same_when_equal() {
+    let evaluations = [
+        Evaluation::Loss(1), Evaluation::Loss(3), Evaluation::Heuristic(-5), Evaluation::Heuristic(0),
+        Evaluation::Draw, Evaluation::Heuristic(2), Evaluation::Win(4), Evaluation::Win(2),
+    ];
+    for (i, a) in evaluations.iter().enumerate() {
+        for (j, b) in evaluations.iter().enumerate() {
+            assert_eq!(a.cmp(b), i.cmp(&j), "{:?} against {:?}", a, b);
+            assert_eq!(a == b, a.cmp(b) == Ordering::Equal);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Builds a ConnectN opening book by solving every position up to a given
+// number of plies. Usage:
+//
+//     build_book <columns> <rows> <win_length> <plies> <output file>
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+use tictactoe::book::Book;
+use tictactoe::game::Game;
+use tictactoe::solver::Solver;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 6 {
+        eprintln!("Usage: {} <columns> <rows> <win_length> <plies> <output file>", args[0]);
+        process::exit(2);
+    }
+    let numbers: Vec<usize> = args[1..5].iter()
+        .map(|arg| arg.parse().unwrap_or_else(|_| {
+            eprintln!("Not a number: {}", arg);
+            process::exit(2)
+        }))
+        .collect();
+    let (columns, rows, win_length, plies) = (numbers[0], numbers[1], numbers[2], numbers[3]);
+    if !Solver::fits(columns, rows) {
+        eprintln!("{}x{} is too large for the solver", columns, rows);
+        process::exit(2);
+    }
+
+    let positions = Book::positions(columns, rows, win_length, plies);
+    println!("Solving {} positions up to {} plies", positions.len(), plies);
+
+    let mut solver = Solver::new(columns, rows, win_length);
+    let mut book = Book::new(columns, rows, win_length);
+    let start = Instant::now();
+    // Deepest positions first: they're quickest, and fill the solver's
+    // table for the shallower ones.
+    for (done, position) in positions.iter().rev().enumerate() {
+        let player = position.current_player();
+        if let Some((column, evaluation)) = solver.best_move(position, player) {
+            book.insert(position.board(), column, evaluation);
+        }
+        if (done + 1) % 100 == 0 {
+            println!("{}/{} positions, {:.1?}", done + 1, positions.len(), start.elapsed());
+        }
+    }
+
+    if let Err(error) = book.save(Path::new(&args[5])) {
+        eprintln!("Couldn't write {}: {}", args[5], error);
+        process::exit(1);
+    }
+    println!("Wrote {} positions to {} in {:.1?}", book.len(), args[5], start.elapsed());
+}
@@ -0,0 +1,124 @@
+// Plays two computer players against each other and prints the results.
+// Usage:
+//
+//     selfplay tictactoe --size <n> --first <engine> --second <engine> [--games <n>] [--seed <n>]
+//     selfplay connectn --size <columns>x<rows> --win <length> --first <engine> --second <engine> [--games <n>] [--seed <n>]
+//
+// Engines are beginner, casual, strong, perfect, mcts:<iterations>,
+// lookahead:<depth> or deepening:<milliseconds>. The same seed replays the
+// same games, except with deepening, which depends on the clock.
+use std::process;
+
+use tictactoe::agent::Agent;
+use tictactoe::board::Board;
+use tictactoe::connectn::ConnectN;
+use tictactoe::evaluator::Evaluator;
+use tictactoe::lookahead::{Heuristic, Lookahead};
+use tictactoe::minimax::Minimax;
+use tictactoe::selfplay::{engine_seeds, play_match, EngineSpec};
+use tictactoe::solver::Solver;
+use tictactoe::tablebase::Tablebase;
+use tictactoe::threats::ThreatGuided;
+use tictactoe::tictactoe::Tictactoe;
+
+struct Options {
+    game: String,
+    size: (usize, usize),
+    win_length: Option<usize>,
+    first: EngineSpec,
+    second: EngineSpec,
+    games: usize,
+    seed: u64,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: selfplay tictactoe --size <n> --first <engine> --second <engine> [--games <n>] [--seed <n>]");
+    eprintln!("       selfplay connectn --size <columns>x<rows> --win <length> --first <engine> --second <engine> \
+        [--games <n>] [--seed <n>]");
+    eprintln!("Engines: beginner, casual, strong, perfect, mcts:<iterations>, lookahead:<depth>, deepening:<ms>");
+    process::exit(2)
+}
+
+fn parse<T: std::str::FromStr>(value: Option<String>) -> T {
+    value.and_then(|value| value.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn parse_options() -> Options {
+    let mut args = std::env::args().skip(1);
+    let game = args.next().unwrap_or_else(|| usage());
+    let mut size = None;
+    let mut win_length = None;
+    let mut first = None;
+    let mut second = None;
+    let mut games = 100;
+    let mut seed = 0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => {
+                let value = args.next().unwrap_or_else(|| usage());
+                size = Some(match value.split_once('x') {
+                    Some((columns, rows)) => (parse(Some(columns.to_string())), parse(Some(rows.to_string()))),
+                    None => {
+                        let size = parse(Some(value));
+                        (size, size)
+                    },
+                });
+            },
+            "--win" => win_length = Some(parse(args.next())),
+            "--first" => first = Some(parse(args.next())),
+            "--second" => second = Some(parse(args.next())),
+            "--games" => games = parse(args.next()),
+            "--seed" => seed = parse(args.next()),
+            _ => usage(),
+        }
+    }
+    match (size, first, second) {
+        (Some(size), Some(first), Some(second)) => Options { game, size, win_length, first, second, games, seed },
+        _ => usage(),
+    }
+}
+
+fn tictactoe_agent(spec: EngineSpec, size: usize, tablebase: &Option<Tablebase>, seed: u64) -> Box<dyn Agent<Tictactoe>> {
+    spec.build(|| match tablebase {
+        Some(tablebase) => Box::new(tablebase.clone()),
+        None => Box::new(Minimax::for_board_size(size)),
+    }, || None, seed)
+}
+
+fn connectn_agent(spec: EngineSpec, game: &ConnectN, seed: u64) -> Box<dyn Agent<ConnectN>> {
+    let heuristic = || Some(Box::new(Evaluator::for_game(game)) as Box<dyn Heuristic<ConnectN>>);
+    spec.build(|| {
+        if Solver::fits(game.board().columns(), game.board().rows()) {
+            Box::new(Solver::for_game(game))
+        } else {
+            Box::new(ThreatGuided::new(4, Lookahead::with_heuristic(4, heuristic().unwrap())))
+        }
+    }, heuristic, seed)
+}
+
+fn main() {
+    let options = parse_options();
+    let (first_seed, second_seed) = engine_seeds(options.seed);
+    let (description, summary) = match (options.game.as_str(), options.size, options.win_length) {
+        ("tictactoe", (size, rows), None) if size == rows => {
+            let game = Tictactoe::new(Board::new(size));
+            let uses_tablebase = [options.first, options.second].iter()
+                .any(|&spec| matches!(spec, EngineSpec::Level(_)));
+            let tablebase = if size <= 4 && uses_tablebase { Some(Tablebase::generate(size)) } else { None };
+            let mut first = tictactoe_agent(options.first, size, &tablebase, first_seed);
+            let mut second = tictactoe_agent(options.second, size, &tablebase, second_seed);
+            (format!("{}x{} Tictactoe", size, size), play_match(&game, first.as_mut(), second.as_mut(), options.games))
+        },
+        ("connectn", (columns, rows), Some(win_length)) => {
+            let game = ConnectN::new(columns, rows, win_length);
+            let mut first = connectn_agent(options.first, &game, first_seed);
+            let mut second = connectn_agent(options.second, &game, second_seed);
+            (format!("{}x{} connect {}", columns, rows, win_length), play_match(&game, first.as_mut(), second.as_mut(), options.games))
+        },
+        _ => usage(),
+    };
+
+    println!("{} games of {}, {} against {}, seed {}", options.games, description, options.first, options.second, options.seed);
+    println!("Counted for {}, which moves first in even-numbered games:", options.first);
+    print!("{}", summary);
+}
@@ -0,0 +1,39 @@
+// Generates the Tictactoe tablebase for a small board and prints how many
+// positions it holds and how they turn out with perfect play, saving it if
+// given a file for the game to load. Usage:
+//
+//     tablebase <board size> [<output file>]
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+
+use tictactoe::tablebase::Tablebase;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let size: usize = match args.get(1).map(|arg| arg.parse()) {
+        Some(Ok(size)) if args.len() <= 3 && (1..=4).contains(&size) => size,
+        _ => {
+            eprintln!("Usage: {} <board size, 1 to 4> [<output file>]", args[0]);
+            process::exit(2);
+        },
+    };
+
+    let start = Instant::now();
+    let tablebase = Tablebase::generate(size);
+    println!("Generated the {}x{} tablebase in {:.1?}", size, size, start.elapsed());
+
+    let summary = tablebase.summary();
+    println!("Reachable:             {}", summary.all);
+    println!("Up to symmetry:        {}", summary.canonical);
+    println!("Finished:              {}", summary.terminal);
+    println!("Finished, up to symm.: {}", summary.canonical_terminal);
+
+    if let Some(path) = args.get(2) {
+        if let Err(error) = tablebase.save(Path::new(path)) {
+            eprintln!("Couldn't save the tablebase to {}: {}", path, error);
+            process::exit(1);
+        }
+        println!("Saved to {}", path);
+    }
+}
@@ -0,0 +1,108 @@
+// Teaches a Tictactoe player by TD(0) learning and saves what it learned.
+// Usage:
+//
+//     train --output <value table> [--size <n>] [--epochs <n>] [--games <per epoch>]
+//           [--opponent self|random|<engine>] [--alpha <rate>] [--epsilon <rate>]
+//           [--seed <n>] [--curve <csv file>]
+//
+// After each epoch the learner plays a random player and its results go to
+// the learning curve, as CSV. Engines are as for selfplay. Runs with the same
+// seed learn the same table.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+
+use tictactoe::agent::Agent;
+use tictactoe::board::Player;
+use tictactoe::learning::{against_random, RandomPlayer, Trainer};
+use tictactoe::minimax::Minimax;
+use tictactoe::selfplay::{engine_seeds, EngineSpec};
+use tictactoe::tictactoe::Tictactoe;
+
+// Games against the random player after each epoch.
+const TEST_GAMES: usize = 500;
+
+fn usage() -> ! {
+    eprintln!("Usage: train --output <value table> [--size <n>] [--epochs <n>] [--games <per epoch>] \
+        [--opponent self|random|<engine>] [--alpha <rate>] [--epsilon <rate>] [--seed <n>] [--curve <csv file>]");
+    process::exit(2)
+}
+
+fn parse<T: std::str::FromStr>(value: Option<String>) -> T {
+    value.and_then(|value| value.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut output = None;
+    let mut curve = None;
+    let mut size = 3;
+    let mut epochs = 20;
+    let mut games = 1000;
+    let mut opponent = "self".to_string();
+    let mut alpha = 0.2;
+    let mut epsilon = 0.1;
+    let mut seed = 0;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(PathBuf::from(parse::<String>(args.next()))),
+            "--curve" => curve = Some(PathBuf::from(parse::<String>(args.next()))),
+            "--size" => size = parse(args.next()),
+            "--epochs" => epochs = parse(args.next()),
+            "--games" => games = parse(args.next()),
+            "--opponent" => opponent = parse(args.next()),
+            "--alpha" => alpha = parse(args.next()),
+            "--epsilon" => epsilon = parse(args.next()),
+            "--seed" => seed = parse(args.next()),
+            _ => usage(),
+        }
+    }
+    let output = output.unwrap_or_else(|| usage());
+
+    let (trainer_seed, opponent_seed) = engine_seeds(seed);
+    let opponent: Option<Box<dyn Agent<Tictactoe>>> = match opponent.as_str() {
+        "self" => None,
+        "random" => Some(Box::new(RandomPlayer::new(opponent_seed))),
+        spec => {
+            let spec: EngineSpec = spec.parse().unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                usage()
+            });
+            Some(spec.build(|| Box::new(Minimax::for_board_size(size)), || None, opponent_seed))
+        },
+    };
+
+    let mut csv: Box<dyn Write> = match &curve {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|error| {
+            eprintln!("Can't write {}: {}", path.display(), error);
+            process::exit(1)
+        }))),
+        None => Box::new(std::io::sink()),
+    };
+    writeln!(csv, "epoch,games,win_rate,draw_rate,loss_rate,positions").unwrap();
+
+    let mut opponent = opponent;
+    let mut trainer = Trainer::new(size, alpha, epsilon, trainer_seed);
+    for epoch in 1..=epochs {
+        for round in 0..games {
+            let learner = if round % 2 == 0 { Player::X } else { Player::O };
+            trainer.play_game(opponent.as_mut().map(|opponent| opponent.as_mut() as &mut dyn Agent<Tictactoe>), learner);
+        }
+        // The same random games each epoch, so only the learner changes.
+        let summary = against_random(trainer.table(), TEST_GAMES, seed);
+        let rate = |count: usize| count as f64 / TEST_GAMES as f64;
+        println!("Epoch {}: {:.1}% wins, {:.1}% draws, {:.1}% losses against random, {} positions known",
+            epoch, 100.0 * rate(summary.wins), 100.0 * rate(summary.draws), 100.0 * rate(summary.losses),
+            trainer.table().len());
+        writeln!(csv, "{},{},{:.4},{:.4},{:.4},{}", epoch, epoch * games,
+            rate(summary.wins), rate(summary.draws), rate(summary.losses), trainer.table().len()).unwrap();
+    }
+    csv.flush().unwrap();
+
+    if let Err(error) = trainer.table().save(&output) {
+        eprintln!("Can't write {}: {}", output.display(), error);
+        process::exit(1);
+    }
+    println!("Saved {} positions to {}", trainer.table().len(), output.display());
+}
@@ -1,9 +1,151 @@
 use std::fmt;
+use std::sync::Arc;
+
+use crate::zobrist::{Zobrist, DEFAULT_SEED};
+
+// A player, numbered from 0 in the order their marks are listed. Two-player
+// games are between X and O, who are players 0 and 1.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Player(u8);
+
+impl Player {
+    pub const X: Player = Player(0);
+    pub const O: Player = Player(1);
+    // The most players any game takes.
+    pub const MAX: usize = 4;
+    // The marks players are drawn with unless told otherwise.
+    pub const MARKS: [char; Player::MAX] = ['X', 'O', 'Y', 'Z'];
+
+    pub fn new(index: usize) -> Player {
+        assert!(index < Player::MAX, "There can be at most {} players", Player::MAX);
+        Player(index as u8)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    // The other player of a two-player game.
+    pub fn opponent(self) -> Player {
+        match self {
+            Player::X => Player::O,
+            Player::O => Player::X,
+            _ => panic!("Player {} has more than one opponent", self),
+        }
+    }
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Player::MARKS[self.index()])
+    }
+}
+
+impl fmt::Debug for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Player {
-    X,
-    O,
+pub enum PlayersError {
+    Count(usize),
+    // Marks must be printable and all different.
+    BadMark(char),
+    // A turn order has to name every player once.
+    BadOrder,
+}
+
+impl fmt::Display for PlayersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayersError::Count(count) => write!(f, "{} players can't play, only 2 to {}", count, Player::MAX),
+            PlayersError::BadMark(mark) => write!(f, "{:?} can't be a mark", mark),
+            PlayersError::BadOrder => write!(f, "The turn order has to name every player once"),
+        }
+    }
+}
+
+// Who is playing a game: the mark each player is drawn with, and the order
+// they take turns in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Players {
+    marks: Vec<char>,
+    order: Vec<Player>,
+}
+
+impl Players {
+    // `count` players with the usual marks, taking turns in number order.
+    pub fn new(count: usize) -> Players {
+        Players::from_marks(&Player::MARKS[..count].iter().collect::<String>()).unwrap()
+    }
+
+    // A player for each of `marks`, taking turns in the order they're
+    // listed.
+    pub fn from_marks(marks: &str) -> Result<Players, PlayersError> {
+        let marks: Vec<char> = marks.chars().collect();
+        if marks.len() < 2 || marks.len() > Player::MAX {
+            return Err(PlayersError::Count(marks.len()))
+        }
+        for (index, &mark) in marks.iter().enumerate() {
+            if mark.is_whitespace() || mark.is_control() || marks[..index].contains(&mark) {
+                return Err(PlayersError::BadMark(mark))
+            }
+        }
+        let order = (0..marks.len()).map(Player::new).collect();
+        Ok(Players { marks, order })
+    }
+
+    // The same players taking turns in another order, given by their marks.
+    pub fn in_order(self, order: &str) -> Result<Players, PlayersError> {
+        let order: Option<Vec<Player>> = order.chars().map(|mark| self.player_marked(mark)).collect();
+        match order {
+            Some(order) if order.len() == self.count() && (0..self.count()).all(|index| order.contains(&Player::new(index))) =>
+                Ok(Players { order, ..self }),
+            _ => Err(PlayersError::BadOrder),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.marks.len()
+    }
+
+    pub fn mark(&self, player: Player) -> char {
+        self.marks[player.index()]
+    }
+
+    pub fn player_marked(&self, mark: char) -> Option<Player> {
+        self.marks.iter().position(|&other| other == mark).map(Player::new)
+    }
+
+    // The players in the order they take turns.
+    pub fn order(&self) -> &[Player] {
+        &self.order
+    }
+
+    // Whose turn it is once `moves` moves have been made.
+    pub fn to_move(&self, moves: usize) -> Player {
+        self.order[moves % self.order.len()]
+    }
+
+    // X and O with X to start, as the computer players expect. Marks don't
+    // matter.
+    pub fn is_two_player(&self) -> bool {
+        self.order == [Player::X, Player::O]
+    }
+
+    // How to draw each player's squares, for `Annotated::with_marks`.
+    pub fn square_marks(&self) -> Vec<(Square, String)> {
+        self.marks.iter().enumerate()
+            .map(|(index, mark)| (Square::Played(Player::new(index)), mark.to_string()))
+            .collect()
+    }
+}
+
+impl Default for Players {
+    fn default() -> Players {
+        Players::new(2)
+    }
 }
 
 // Basically Option<Player>, but can implement Display for it
@@ -14,89 +156,300 @@ pub enum Square {
 }
 
 impl Square {
-    pub fn to_option(&self) -> Option<Player> {
+    pub fn to_option(self) -> Option<Player> {
         match self {
             Square::Empty => None,
-            Square::Played(player) => Some(*player)
+            Square::Played(player) => Some(player)
+        }
+    }
+}
+
+// What the squares of a board can hold: nothing, or one of `KINDS` kinds of
+// piece. Each kind has its own Zobrist keys.
+pub trait Cell: Copy + PartialEq + fmt::Display {
+    const KINDS: usize;
+
+    fn empty() -> Self;
+
+    // Which kind of piece is here, counting from 0 and below KINDS, or None
+    // if nothing is.
+    fn kind(&self) -> Option<usize>;
+
+    fn is_empty(&self) -> bool {
+        self.kind().is_none()
+    }
+}
+
+impl Cell for Square {
+    const KINDS: usize = Player::MAX;
+
+    fn empty() -> Square {
+        Square::Empty
+    }
+
+    fn kind(&self) -> Option<usize> {
+        self.to_option().map(Player::index)
+    }
+}
+
+// A straight run of squares: `length` of them from `start`, each `step`
+// on from the one before, as (column, row, layer). Lines on flat boards
+// stay in layer 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Line {
+    pub start: (usize, usize, usize),
+    pub step: (isize, isize, isize),
+    pub length: usize,
+    // Whether the board has layers, so the line's layer is worth printing.
+    layered: bool,
+    // The board's columns and rows, if the line may run off one edge and
+    // back on at the other.
+    wraps: Option<(usize, usize)>,
+}
+
+// The ways lines run, each taken the way the board's iterators walk it:
+// forward and backward diagonals, rows and columns.
+pub const DIRECTIONS: [(isize, isize); 4] = [(1, 1), (-1, 1), (1, 0), (0, 1)];
+
+impl Line {
+    pub fn flat(start: (usize, usize), step: (isize, isize), length: usize) -> Line {
+        Line { start: (start.0, start.1, 0), step: (step.0, step.1, 0), length, layered: false, wraps: None }
+    }
+
+    pub fn layered(start: (usize, usize, usize), step: (isize, isize, isize), length: usize) -> Line {
+        Line { start, step, length, layered: true, wraps: None }
+    }
+
+    // The same line on a board of `columns` by `rows` that wraps around.
+    pub fn wrapped(mut self, columns: usize, rows: usize) -> Line {
+        self.wraps = Some((columns, rows));
+        self
+    }
+
+    pub fn cells(&self) -> Vec<(usize, usize, usize)> {
+        (0..self.length).map(|i| self.cell(i)).collect()
+    }
+
+    pub fn end(&self) -> (usize, usize, usize) {
+        self.cell(self.length.saturating_sub(1))
+    }
+
+    fn cell(&self, i: usize) -> (usize, usize, usize) {
+        let i = i as isize;
+        let (mut column, mut row) = (self.start.0 as isize + i * self.step.0, self.start.1 as isize + i * self.step.1);
+        if let Some((columns, rows)) = self.wraps {
+            column = column.rem_euclid(columns as isize);
+            row = row.rem_euclid(rows as isize);
+        }
+        (column as usize, row as usize, (self.start.2 as isize + i * self.step.2) as usize)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.end();
+        if self.layered {
+            write!(f, "{}, {}, {} to {}, {}, {}", self.start.0, self.start.1, self.start.2, end.0, end.1, end.2)
+        } else {
+            write!(f, "{}, {} to {}, {}", self.start.0, self.start.1, end.0, end.1)
         }
     }
 }
 
+// The ways a board can be turned or reflected onto itself, as a map from
+// each square to the one it lands on. Rotations are clockwise, as the board
+// is printed, and only make sense on square boards.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // Left to right.
+    MirrorColumns,
+    // Top to bottom.
+    MirrorRows,
+    // About the diagonal through (0, 0).
+    Transpose,
+    // About the other diagonal.
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::MirrorColumns,
+        Symmetry::MirrorRows,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    // Where (column, row) of a board of the given size ends up.
+    pub fn apply(self, (column, row): (usize, usize), columns: usize, rows: usize) -> (usize, usize) {
+        let last_column = columns - 1;
+        let last_row = rows - 1;
+        match self {
+            Symmetry::Identity => (column, row),
+            Symmetry::Rotate90 => (last_row - row, column),
+            Symmetry::Rotate180 => (last_column - column, last_row - row),
+            Symmetry::Rotate270 => (row, last_column - column),
+            Symmetry::MirrorColumns => (last_column - column, row),
+            Symmetry::MirrorRows => (column, last_row - row),
+            Symmetry::Transpose => (row, column),
+            Symmetry::AntiTranspose => (last_row - row, last_column - column),
+        }
+    }
+
+    // The symmetry that undoes this one, e.g. to map a move found on a
+    // canonical board back onto the board it came from.
+    pub fn inverse(self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            symmetry => symmetry,
+        }
+    }
+}
+
+// Which edges of a board lines may run across, coming back on at the
+// opposite edge: none, the left and right edges of a cylinder, or all four
+// edges of a torus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Wrap {
+    #[default]
+    Flat,
+    Cylinder,
+    Torus,
+}
+
+impl Wrap {
+    pub fn left_right(self) -> bool {
+        self != Wrap::Flat
+    }
+
+    pub fn top_bottom(self) -> bool {
+        self == Wrap::Torus
+    }
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
 #[derive(Debug, Clone)]
-pub struct Board {
-    cells: Vec<Vec<Square>>
+pub struct Board<C = Square> {
+    cells: Vec<Vec<C>>,
+    wrap: Wrap,
+    // Zobrist hash of the marks on the board, kept up to date as squares
+    // are set. Copies of a board share its keys.
+    hash: u64,
+    zobrist: Arc<Zobrist>,
 }
 
 #[derive(Debug)]
-pub struct BoardIterator<'a> {
-    board: &'a Board,
+pub struct BoardIterator<'a, C = Square> {
+    board: &'a Board<C>,
     row: usize,
     column: usize,
     row_direction: i8,
     column_direction: i8,
+    // Squares left to visit. Lines that wrap around never reach an edge, so
+    // they stop once they've gone round and on far enough to see every run
+    // across the seam.
+    remaining: usize,
 }
 
-fn add_usize_i8(u: usize, i: i8) -> usize {
-    (u as isize + i as isize) as usize
-}
+impl<C: Cell> BoardIterator<'_, C> {
+    // How far each square is from the one before, as (column, row).
+    pub fn step(&self) -> (isize, isize) {
+        (self.column_direction as isize, self.row_direction as isize)
+    }
 
-impl Iterator for BoardIterator<'_> {
-    type Item = Square;
+    // The number of different squares on the line, if it wraps round onto
+    // itself. Past that many, the iterator repeats them.
+    pub fn loop_length(&self) -> Option<usize> {
+        self.board.loop_length(self.step())
+    }
 
-    fn next(&mut self) -> Option<Square> {
+    // Like `next`, but also says where the square is, as (column, row).
+    pub fn next_with_position(&mut self) -> Option<((usize, usize), C)> {
         let columns = self.board.columns();
         let rows = self.board.rows();
-        if self.column >= columns || self.row >= rows {
+        if self.remaining == 0 || self.column >= columns || self.row >= rows {
             return None
         }
 
-        let square = self.board.get_square(self.column, self.row);
-        self.column = add_usize_i8(self.column, self.column_direction);
-        self.row = add_usize_i8(self.row, self.row_direction);
+        let position = (self.column, self.row);
+        let square = self.board.get_square(self.column, self.row)?;
+        self.remaining -= 1;
+        match self.board.offset(position, self.step(), 1) {
+            Some((column, row)) => {
+                self.column = column;
+                self.row = row;
+            },
+            None => self.remaining = 0,
+        }
+
+        Some((position, square))
+    }
+}
+
+impl<C: Cell> Iterator for BoardIterator<'_, C> {
+    type Item = C;
 
-        square
+    fn next(&mut self) -> Option<C> {
+        self.next_with_position().map(|(_, square)| square)
     }
 }
 
 #[derive(Debug)]
-pub struct DiagonalIteratorIterator<'a> {
-    board: &'a Board,
+pub struct DiagonalIteratorIterator<'a, C = Square> {
+    board: &'a Board<C>,
     minimum_size: usize,
     row: usize,
     column: usize,
     forward: bool,
 }
 
-impl <'a> DiagonalIteratorIterator<'a> {
-    pub fn new(board: &'a Board, minimum_size: usize) -> Option<DiagonalIteratorIterator<'a>> {
+impl <'a, C: Cell> DiagonalIteratorIterator<'a, C> {
+    pub fn new(board: &'a Board<C>, minimum_size: usize) -> Option<DiagonalIteratorIterator<'a, C>> {
         let mut dii = DiagonalIteratorIterator {
             board,
             minimum_size,
-            row: board.rows() - 1,
+            row: if board.wrap.left_right() { 0 } else { board.rows() - 1 },
             column: 0,
             forward: true,
         };
 
-        loop {
-            match dii.calculate_diagonal_length() {
-                None =>
-                    return None,
-                Some(length) if length >= minimum_size =>
-                    return Some(dii),
-                Some(_) => {
-                    let next = dii.next();
-
-                    if next.is_none() {
-                        return None
-                    }
-                }
-            }
+        if dii.calculate_diagonal_length()? < minimum_size && !dii.advance() {
+            return None
         }
+        Some(dii)
+    }
+
+    // On boards that wrap left to right, every diagonal crosses the top row,
+    // so they're all taken from there. On a torus each comes back round
+    // through several of its squares, and only one is needed.
+    fn wrapped_origins(&self) -> Option<usize> {
+        if !self.board.wrap.left_right() {
+            return None
+        }
+        Some(match self.board.loop_length((1, 1)) {
+            Some(length) => self.board.columns() * self.board.rows() / length,
+            None => self.board.columns(),
+        })
     }
 
     fn calculate_diagonal_length(&self) -> Option<usize> {
-        //println!("calculating diagonal length column {}, row {}, board width {}, board height {}",
-        //    self.column, self.row, self.board.columns(), self.board.rows());
+        if let Some(origins) = self.wrapped_origins() {
+            if self.column >= origins {
+                return None
+            }
+            return Some(self.board.loop_length((1, 1)).unwrap_or(self.board.rows()))
+        }
         let mut total = 0;
         let mut column = self.column;
         let mut row = self.row;
@@ -104,7 +457,6 @@ impl <'a> DiagonalIteratorIterator<'a> {
         let rows = self.board.rows();
 
         if column >= columns || row >= rows {
-            //("Off the board");
             return None
         }
         if self.forward {
@@ -113,8 +465,7 @@ impl <'a> DiagonalIteratorIterator<'a> {
                 row += 1;
                 total += 1;
             }
-            //println!("Forward: {}", total);
-            return Some(total);
+            Some(total)
         } else {
             while column > 0 && row < rows {
                 column -= 1;
@@ -122,21 +473,32 @@ impl <'a> DiagonalIteratorIterator<'a> {
                 total += 1;
             }
             if column == 0 && row < rows {
-                //println!("Backward: {}", total + 1);
                 return Some(total + 1)
             }
-            //println!("Backward: {}", total);
-            return Some(total)
+            Some(total)
         }
     }
 
     fn increment_origin(&mut self) -> bool {
-        //println!("incrementing origin {} {}", self.column, self.row);
         if self.column > self.board.columns() || self.row > self.board.rows() {
-            //println!("Incrementing failed: Off the board");
             return false
         }
 
+        if let Some(origins) = self.wrapped_origins() {
+            if self.forward && self.column + 1 < origins {
+                self.column += 1;
+            } else if self.forward {
+                self.forward = false;
+                self.column = origins - 1;
+            } else if self.column == 0 {
+                self.column = usize::MAX;
+                return false
+            } else {
+                self.column -= 1;
+            }
+            return true
+        }
+
         if self.row > 0 {
             self.row -= 1;
         } else {
@@ -150,60 +512,130 @@ impl <'a> DiagonalIteratorIterator<'a> {
             } else {
                 if self.column == 0 {
                     self.column = usize::MAX;
-                    //println!("Reached 0,0 for the second time, iteration completed.");
                     return false
                 }
                 self.column -= 1;
             }
         }
-        //println!("Incrementing succeeded, new value: {} {}", self.column, self.row);
         true
     }
-}
-
-impl <'a> Iterator for DiagonalIteratorIterator<'a> {
-    type Item = BoardIterator<'a>;
-
-    fn next(&mut self) -> Option<BoardIterator<'a>> {
-        if !self.increment_origin() {
-            return None;
-        }
 
+    // Moves on to the next origin whose diagonal is long enough, or past
+    // the last one.
+    fn advance(&mut self) -> bool {
         loop {
+            if !self.increment_origin() {
+                return false
+            }
             match self.calculate_diagonal_length() {
-                None =>
-                    return None,
-                Some(length) => {
-                    //println!("Diagonal length is {}, minimum size is {}", length, self.minimum_size);
-                    if length >= self.minimum_size {
-                        //println!("Retrieving BoardIterator: column {}, row {}", self.column, self.row);
-                        let result = Some(BoardIterator {
-                            board: self.board,
-                            row: self.row,
-                            column: self.column,
-                            row_direction: 1,
-                            column_direction: if self.forward { 1 } else { -1 }
-                        });
-                        return result;
-                    } else if !self.increment_origin() {
-                        //println!("End of the line");
-                        return None;
-                    }
-                }
+                None => return false,
+                Some(length) if length >= self.minimum_size => return true,
+                Some(_) => (),
             }
         }
+    }
 
+    fn current(&self) -> BoardIterator<'a, C> {
+        self.board.iterator(self.column, self.row, if self.forward { 1 } else { -1 }, 1)
     }
 }
 
+// Yields the diagonal at the current origin, then moves on, so the first
+// diagonal comes first.
+impl <'a, C: Cell> Iterator for DiagonalIteratorIterator<'a, C> {
+    type Item = BoardIterator<'a, C>;
+
+    fn next(&mut self) -> Option<BoardIterator<'a, C>> {
+        self.calculate_diagonal_length()?;
+        let diagonal = self.current();
+        self.advance();
+        Some(diagonal)
+    }
+}
+
+// A column's name in move notation: a to z, then aa, ab and so on, as
+// spreadsheets name them.
+pub fn column_name(column: usize) -> String {
+    let mut name = Vec::new();
+    let mut rest = column + 1;
+    while rest > 0 {
+        rest -= 1;
+        name.push(b'a' + (rest % 26) as u8);
+        rest /= 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+// Boards of Squares, as Tictactoe and ConnectN are played on. Kept apart
+// from the generic constructor so that `Board::new` needs no annotations.
 impl Board {
     // indexing is [x, y] which is to say [column, row] not [row, column]
     pub fn new(size: usize) -> Board {
-        Board { cells: vec![vec![Square::Empty; size]; size] }
+        Board::new_rectangle(size, size)
     }
 
     pub fn new_rectangle(columns: usize, rows: usize) -> Board {
-        Board { cells: vec![vec![Square::Empty; rows]; columns] }
+        Board::with_size(columns, rows)
+    }
+
+    // X always moves first, so O is to move whenever X has played more.
+    pub fn current_player(&self) -> Player {
+        let mut balance: isize = 0;
+        for column in &self.cells {
+            for square in column {
+                match square {
+                    Square::Played(Player::X) => balance += 1,
+                    Square::Played(Player::O) => balance -= 1,
+                    _ => (),
+                }
+            }
+        }
+        if balance > 0 { Player::O } else { Player::X }
+    }
+}
+
+impl<C: Cell> Board<C> {
+    // An empty board of any kind of cell.
+    pub fn with_size(columns: usize, rows: usize) -> Board<C> {
+        Board {
+            cells: vec![vec![C::empty(); rows]; columns],
+            wrap: Wrap::Flat,
+            hash: 0,
+            zobrist: Arc::new(Zobrist::new(columns, rows, C::KINDS, DEFAULT_SEED)),
+        }
+    }
+
+    // The same board hashed with keys drawn from another seed.
+    pub fn with_seed(mut self, seed: u64) -> Board<C> {
+        self.zobrist = Arc::new(Zobrist::new(self.columns(), self.rows(), C::KINDS, seed));
+        self.hash = 0;
+        for column in 0..self.columns() {
+            for row in 0..self.rows() {
+                if let Some(kind) = self.cells[column][row].kind() {
+                    self.hash ^= self.zobrist.piece(column, row, kind);
+                }
+            }
+        }
+        self
+    }
+
+    // The same board, with lines running round the edges `wrap` joins.
+    pub fn with_wrap(mut self, wrap: Wrap) -> Board<C> {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn zobrist(&self) -> &Zobrist {
+        &self.zobrist
     }
 
     pub fn columns(&self) -> usize {
@@ -211,10 +643,10 @@ impl Board {
     }
 
     pub fn rows(&self) -> usize {
-        self.cells.get(0).unwrap().len()
+        self.cells.first().unwrap().len()
     }
 
-    pub fn get_square(&self, column: usize, row: usize) -> Option<Square> {
+    pub fn get_square(&self, column: usize, row: usize) -> Option<C> {
         if column >= self.columns() || row >= self.rows() {
             None
         } else {
@@ -222,34 +654,72 @@ impl Board {
         }
     }
 
-    pub fn set_square(&mut self, column: usize, row: usize, square: Square) -> &mut Self {
+    pub fn set_square(&mut self, column: usize, row: usize, square: C) -> &mut Self {
+        for old_or_new in [self.cells[column][row], square] {
+            if let Some(kind) = old_or_new.kind() {
+                self.hash ^= self.zobrist.piece(column, row, kind);
+            }
+        }
         self.cells[column][row] = square;
 
         self
     }
 
-    fn iterator(&self, column: usize, row: usize, column_direction: i8, row_direction: i8) -> BoardIterator {
+    fn iterator(&self, column: usize, row: usize, column_direction: i8, row_direction: i8) -> BoardIterator<'_, C> {
+        let remaining = match self.loop_length((column_direction as isize, row_direction as isize)) {
+            Some(length) => 2 * length - 1,
+            None => usize::MAX,
+        };
         BoardIterator {
             board: self,
             column,           row,
-            column_direction, row_direction
+            column_direction, row_direction,
+            remaining
+        }
+    }
+
+    // The square `times` steps on from (column, row), coming back round
+    // the edges the board wraps at, or None if that's off the board.
+    fn offset(&self, (column, row): (usize, usize), step: (isize, isize), times: isize) -> Option<(usize, usize)> {
+        let mut column = column as isize + times * step.0;
+        let mut row = row as isize + times * step.1;
+        if self.wrap.left_right() {
+            column = column.rem_euclid(self.columns() as isize);
+        }
+        if self.wrap.top_bottom() {
+            row = row.rem_euclid(self.rows() as isize);
+        }
+        if column < 0 || row < 0 || column as usize >= self.columns() || row as usize >= self.rows() {
+            return None
         }
+        Some((column as usize, row as usize))
     }
 
-    pub fn row_iterator(&self, row: usize) -> BoardIterator {
+    // How many squares a line with this step goes through before coming
+    // back to where it started, or None if it runs into an edge.
+    pub fn loop_length(&self, (columns, rows): (isize, isize)) -> Option<usize> {
+        if (columns != 0 && !self.wrap.left_right()) || (rows != 0 && !self.wrap.top_bottom()) {
+            return None
+        }
+        let column_loop = if columns != 0 { self.columns() } else { 1 };
+        let row_loop = if rows != 0 { self.rows() } else { 1 };
+        Some(column_loop / gcd(column_loop, row_loop) * row_loop)
+    }
+
+    pub fn row_iterator(&self, row: usize) -> BoardIterator<'_, C> {
         self.iterator(
             0, row,
             1, 0
         )
     }
 
-    pub fn column_iterator(&self, column: usize) -> BoardIterator {
+    pub fn column_iterator(&self, column: usize) -> BoardIterator<'_, C> {
         self.iterator(
             column, 0,
             0,      1)
     }
 
-    pub fn diagonal_iterator(&self, column: usize, forward: bool) -> BoardIterator {
+    pub fn diagonal_iterator(&self, column: usize, forward: bool) -> BoardIterator<'_, C> {
         if forward {
             self.iterator(
                 column, 0,
@@ -263,15 +733,143 @@ impl Board {
         }
     }
 
-    pub fn diagonal_iterator_iterator(&self, minimum_size: usize) -> Option<DiagonalIteratorIterator> {
+    pub fn diagonal_iterator_iterator(&self, minimum_size: usize) -> Option<DiagonalIteratorIterator<'_, C>> {
         DiagonalIteratorIterator::new(self, minimum_size)
     }
 
+    // The longest line of squares holding the same mark as (column, row)
+    // that runs through it with the given step. Its length is zero if the
+    // square is empty. On boards that wrap, a line can go right round but
+    // counts each square once, and one that does starts from its topmost,
+    // then leftmost, square.
+    pub fn run_through(&self, column: usize, row: usize, step: (isize, isize)) -> Line {
+        let square = match self.get_square(column, row) {
+            Some(square) if !square.is_empty() => square,
+            _ => return Line::flat((column, row), step, 0),
+        };
+        let matches = |cell: Option<(usize, usize)>| cell.and_then(|(column, row)| self.get_square(column, row)) == Some(square);
+        let limit = self.loop_length(step).unwrap_or(usize::MAX);
+        let mut start = (column, row);
+        let mut end = (column, row);
+        let mut length = 1;
+        while length < limit && matches(self.offset(start, step, -1)) {
+            start = self.offset(start, step, -1).unwrap();
+            length += 1;
+        }
+        while length < limit && matches(self.offset(end, step, 1)) {
+            end = self.offset(end, step, 1).unwrap();
+            length += 1;
+        }
+        if length == limit {
+            start = (0..limit as isize)
+                .filter_map(|times| self.offset(start, step, times))
+                .min_by_key(|&(column, row)| (row, column))
+                .unwrap();
+        }
+        let line = Line::flat(start, step, length);
+        if self.wrap.left_right() { line.wrapped(self.columns(), self.rows()) } else { line }
+    }
+
+    // The symmetries of the board's shape. Square boards have all eight;
+    // other boards, cylinders, and boards where stones fall to the bottom,
+    // can only be mirrored left to right.
+    pub fn symmetries(&self, gravity: bool) -> Vec<Symmetry> {
+        if !gravity && self.columns() == self.rows() && self.wrap != Wrap::Cylinder {
+            Symmetry::ALL.to_vec()
+        } else {
+            vec![Symmetry::Identity, Symmetry::MirrorColumns]
+        }
+    }
+
+    // A copy of the board with every mark moved by `symmetry`.
+    pub fn transformed(&self, symmetry: Symmetry) -> Board<C> {
+        let mut board = self.clone();
+        board.reset();
+        for column in 0..self.columns() {
+            for row in 0..self.rows() {
+                let (to_column, to_row) = symmetry.apply((column, row), self.columns(), self.rows());
+                board.set_square(to_column, to_row, self.cells[column][row]);
+            }
+        }
+        board
+    }
+
+    // The board under each of its symmetries, the identity first.
+    pub fn symmetric_variants(&self, gravity: bool) -> Vec<(Symmetry, Board<C>)> {
+        self.symmetries(gravity).into_iter()
+            .map(|symmetry| (symmetry, self.transformed(symmetry)))
+            .collect()
+    }
+
+    // The one variant every symmetric copy of this board agrees on, and the
+    // symmetry that takes this board to it. A square (column, row) of the
+    // canonical board is `symmetry.inverse().apply((column, row), ..)` here.
+    pub fn canonical(&self, gravity: bool) -> (Board<C>, Symmetry) {
+        let (symmetry, board) = self.symmetric_variants(gravity).into_iter()
+            .min_by_key(|(_, board)| board.marks())
+            .unwrap();
+        (board, symmetry)
+    }
+
+    // The squares column by column, for ordering boards.
+    fn marks(&self) -> Vec<usize> {
+        self.cells.iter().flatten().map(|square| square.kind().map_or(0, |kind| kind + 1)).collect()
+    }
+
+    // The first run of at least `length` squares holding the same piece,
+    // looking along the diagonals, then the rows, then the columns, and
+    // reaching as far as the run goes.
+    pub fn find_line(&self, length: usize) -> Option<Line> {
+        if let Some(diagonal_iterator_iterator) = self.diagonal_iterator_iterator(length) {
+            for mut diagonal_iterator in diagonal_iterator_iterator {
+                if let Some(line) = self.find_line_along(&mut diagonal_iterator, length) {
+                    return Some(line)
+                }
+            }
+        }
+
+        for row in 0..self.rows() {
+            if let Some(line) = self.find_line_along(&mut self.row_iterator(row), length) {
+                return Some(line)
+            }
+        }
+
+        for column in 0..self.columns() {
+            if let Some(line) = self.find_line_along(&mut self.column_iterator(column), length) {
+                return Some(line)
+            }
+        }
+
+        None
+    }
+
+    // A line that wraps onto itself before `length` squares can't hold that
+    // many different ones, however often it goes round.
+    fn find_line_along(&self, iterator: &mut BoardIterator<'_, C>, length: usize) -> Option<Line> {
+        if iterator.loop_length().is_some_and(|squares| squares < length) {
+            return None
+        }
+        let mut last = C::empty();
+        let mut count = 0;
+        while let Some(((column, row), square)) = iterator.next_with_position() {
+            count = match square {
+                square if square.is_empty() => 0,
+                square if square == last => count + 1,
+                _ => 1,
+            };
+            if count >= length {
+                return Some(self.run_through(column, row, iterator.step()))
+            }
+            last = square;
+        }
+
+        None
+    }
+
     pub fn reset(&mut self) {
-        let size = self.columns();
-        for column in 0..size {
-            for row in 0..size {
-                self.set_square(column, row, Square::Empty);
+        for column in 0..self.columns() {
+            for row in 0..self.rows() {
+                self.set_square(column, row, C::empty());
             }
         }
     }
@@ -279,17 +877,77 @@ impl Board {
 
 impl fmt::Display for Square {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match self { Square::Played(Player::X) => "X", Square::Played(Player::O) => "O", Square::Empty => " "})
+        match self {
+            Square::Played(player) => write!(f, "{}", player),
+            Square::Empty => write!(f, " "),
+        }
+    }
+}
+
+// A board drawn with labels in some of its empty squares and, optionally,
+// above its columns. Squares widen to fit the longest label.
+pub struct Annotated<'a, C = Square> {
+    board: &'a Board<C>,
+    cells: Vec<((usize, usize), String)>,
+    headers: Vec<(usize, String)>,
+    // How to draw particular contents of a square, if not as themselves.
+    marks: Vec<(C, String)>,
+}
+
+impl<C: Cell> Board<C> {
+    pub fn annotated(&self, cells: Vec<((usize, usize), String)>, headers: Vec<(usize, String)>) -> Annotated<'_, C> {
+        Annotated { board: self, cells, headers, marks: Vec::new() }
+    }
+}
+
+impl<C: Cell> Annotated<'_, C> {
+    pub fn with_marks(mut self, marks: Vec<(C, String)>) -> Self {
+        self.marks = marks;
+        self
+    }
+}
+
+impl<C: Cell> fmt::Display for Annotated<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.cells.iter().map(|(_, label)| label)
+            .chain(self.headers.iter().map(|(_, label)| label))
+            .chain(self.marks.iter().map(|(_, mark)| mark))
+            .map(|label| label.chars().count())
+            .max()
+            .unwrap_or(1)
+            .max(1);
+
+        if !self.headers.is_empty() {
+            for col in 0..self.board.columns() {
+                let label = self.headers.iter().find(|(column, _)| *column == col).map_or("", |(_, label)| label);
+                write!(f, " {:^width$}  ", label)?;
+            }
+            writeln!(f)?;
+        }
+        for row in 0..self.board.rows() {
+            for col in 0..self.board.columns() {
+                let label = self.cells.iter().find(|(cell, _)| *cell == (col, row));
+                match (self.board.cells[col][row], label) {
+                    (square, Some((_, label))) if square.is_empty() => write!(f, "[{:^width$}] ", label)?,
+                    (square, _) => match self.marks.iter().find(|(cell, _)| *cell == square) {
+                        Some((_, mark)) => write!(f, "[{:^width$}] ", mark)?,
+                        None => write!(f, "[{:^width$}] ", square.to_string())?,
+                    },
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
     }
 }
 
-impl fmt::Display for Board {
+impl<C: Cell> fmt::Display for Board<C> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for row in 0..self.rows() {
             for col in 0..self.columns() {
                 write!(f, "[{}] ", self.cells[col][row])?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         Ok(())
     }
@@ -308,11 +966,11 @@ fn test_diagonal_iterations() {
     let mut diagonal_iterator_iterator = board.diagonal_iterator_iterator(1).unwrap();
     let mut counter = 0;
     while let Some(_length) = diagonal_iterator_iterator.calculate_diagonal_length() {
-        //println!("test_diagonal_iterations(): length = {}", _length);
         diagonal_iterator_iterator.next();
         counter += 1;
     }
     assert_eq!(counter, 6);
+    assert_eq!(board.diagonal_iterator_iterator(1).unwrap().count(), 6);
 }
 #[test]
 fn diagonal_length_2() {
@@ -344,3 +1002,286 @@ fn diagonal_length_3y() {
     let board = Board::new_rectangle(3, 2);
     assert!(board.diagonal_iterator_iterator(3).is_none());
 }
+#[test]
+fn annotations_widen_the_squares() {
+    let mut board = Board::new_rectangle(3, 2);
+    board.set_square(1, 1, Square::Played(Player::X));
+    let plain = board.to_string();
+    let cells = vec![((0, 1), "W3".to_string()), ((1, 1), "L2".to_string())];
+    assert_eq!(board.annotated(cells, Vec::new()).to_string(),
+        "[  ] [  ] [  ] \n[W3] [X ] [  ] \n");
+    assert_eq!(board.annotated(Vec::new(), vec![(2, "D".to_string())]).to_string(),
+        "         D  \n[ ] [ ] [ ] \n[ ] [X] [ ] \n");
+    // Plain display is untouched.
+    assert_eq!(board.to_string(), plain);
+    assert_eq!(plain, "[ ] [ ] [ ] \n[ ] [X] [ ] \n");
+}
+#[cfg(test)]
+fn random_board(columns: usize, rows: usize, seed: u64) -> Board {
+    let mut rng = crate::rng::Rng::new(seed);
+    let mut board = Board::new_rectangle(columns, rows);
+    for column in 0..columns {
+        for row in 0..rows {
+            board.set_square(column, row, match rng.below(3) {
+                0 => Square::Empty,
+                1 => Square::Played(Player::X),
+                _ => Square::Played(Player::O),
+            });
+        }
+    }
+    board
+}
+#[test]
+fn rectangles_only_mirror_left_to_right() {
+    for &(columns, rows) in &[(4, 3), (3, 4), (7, 6), (1, 5), (5, 1)] {
+        let board = Board::new_rectangle(columns, rows);
+        assert_eq!(board.symmetries(false), vec![Symmetry::Identity, Symmetry::MirrorColumns]);
+        assert_eq!(board.symmetries(true), vec![Symmetry::Identity, Symmetry::MirrorColumns]);
+    }
+    assert_eq!(Board::new(3).symmetries(false), Symmetry::ALL.to_vec());
+    assert_eq!(Board::new(7).symmetries(true), vec![Symmetry::Identity, Symmetry::MirrorColumns]);
+
+    let mut board = Board::new_rectangle(4, 3);
+    board.set_square(0, 2, Square::Played(Player::X));
+    board.set_square(1, 2, Square::Played(Player::O));
+    board.set_square(1, 1, Square::Played(Player::X));
+    let mirrored = board.transformed(Symmetry::MirrorColumns);
+    assert_eq!(mirrored.to_string(), "[ ] [ ] [ ] [ ] \n[ ] [ ] [X] [ ] \n[ ] [ ] [O] [X] \n");
+    assert_eq!(mirrored.transformed(Symmetry::MirrorColumns).to_string(), board.to_string());
+    assert_eq!(board.transformed(Symmetry::Identity).to_string(), board.to_string());
+}
+#[test]
+fn transformed_boards_keep_their_hashes_up_to_date() {
+    for seed in 0..20 {
+        let board = random_board(5, 3, seed);
+        for (_, variant) in board.symmetric_variants(false) {
+            assert_eq!(variant.hash(), variant.clone().with_seed(variant.zobrist().seed()).hash());
+        }
+    }
+}
+#[test]
+fn every_mirror_image_has_the_same_canonical_board() {
+    for &(columns, rows) in &[(4, 3), (3, 5), (7, 6), (2, 1)] {
+        for seed in 0..50 {
+            let board = random_board(columns, rows, seed);
+            let (canonical, symmetry) = board.canonical(true);
+            assert_eq!(board.transformed(symmetry).to_string(), canonical.to_string());
+            // Undoing the symmetry gets the original back.
+            assert_eq!(canonical.transformed(symmetry.inverse()).to_string(), board.to_string());
+            for (_, variant) in board.symmetric_variants(true) {
+                let (other, _) = variant.canonical(true);
+                assert_eq!(other.to_string(), canonical.to_string());
+                assert_eq!(other.hash(), canonical.hash());
+            }
+        }
+    }
+}
+#[test]
+fn moves_on_the_canonical_board_map_back() {
+    // X on the left-hand side is mirrored onto the right.
+    let mut board = Board::new_rectangle(5, 4);
+    board.set_square(0, 3, Square::Played(Player::X));
+    let (canonical, symmetry) = board.canonical(true);
+    assert_eq!(symmetry, Symmetry::MirrorColumns);
+    assert_eq!(canonical.get_square(4, 3), Some(Square::Played(Player::X)));
+    // A reply found on the canonical board, played back on the original.
+    let reply = (3, 3);
+    let (column, row) = symmetry.inverse().apply(reply, canonical.columns(), canonical.rows());
+    assert_eq!((column, row), (1, 3));
+    board.set_square(column, row, Square::Played(Player::O));
+    let mut expected = canonical.clone();
+    expected.set_square(reply.0, reply.1, Square::Played(Player::O));
+    assert_eq!(board.canonical(true).0.to_string(), expected.to_string());
+
+    // Positions that are their own mirror image are already canonical.
+    let mut board = Board::new_rectangle(3, 2);
+    board.set_square(1, 1, Square::Played(Player::X));
+    assert_eq!(board.canonical(true).1, Symmetry::Identity);
+}
+#[test]
+fn square_boards_have_eight_symmetries() {
+    let board = random_board(4, 4, 3);
+    let variants = board.symmetric_variants(false);
+    let distinct: std::collections::HashSet<String> = variants.iter().map(|(_, variant)| variant.to_string()).collect();
+    assert_eq!(distinct.len(), 8);
+    for (symmetry, variant) in &variants {
+        assert_eq!(variant.transformed(symmetry.inverse()).to_string(), board.to_string());
+    }
+    // A quarter turn clockwise takes the top row to the right-hand column.
+    let mut board = Board::new(3);
+    board.set_square(0, 0, Square::Played(Player::X));
+    board.set_square(1, 0, Square::Played(Player::O));
+    let turned = board.transformed(Symmetry::Rotate90);
+    assert_eq!(turned.to_string(), "[ ] [ ] [X] \n[ ] [ ] [O] \n[ ] [ ] [ ] \n");
+    assert_eq!(turned.transformed(Symmetry::Rotate90).to_string(), board.transformed(Symmetry::Rotate180).to_string());
+    let (canonical, symmetry) = turned.canonical(false);
+    assert_eq!(turned.transformed(symmetry).to_string(), canonical.to_string());
+    assert_eq!(canonical.to_string(), board.canonical(false).0.to_string());
+}
+#[cfg(test)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stack {
+    Blocked,
+    Height(u8),
+}
+#[cfg(test)]
+impl fmt::Display for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stack::Blocked => write!(f, "#"),
+            Stack::Height(0) => write!(f, " "),
+            Stack::Height(height) => write!(f, "{}", height),
+        }
+    }
+}
+#[cfg(test)]
+impl Cell for Stack {
+    const KINDS: usize = 4;
+
+    fn empty() -> Stack {
+        Stack::Height(0)
+    }
+
+    fn kind(&self) -> Option<usize> {
+        match self {
+            Stack::Height(0) => None,
+            // Stacks taller than three hash as three.
+            Stack::Height(height) => Some((*height as usize).min(Self::KINDS - 1)),
+            Stack::Blocked => Some(0),
+        }
+    }
+}
+#[test]
+fn boards_hold_other_kinds_of_cell() {
+    let mut board: Board<Stack> = Board::with_size(4, 3);
+    assert_eq!(board.to_string(), "[ ] [ ] [ ] [ ] \n[ ] [ ] [ ] [ ] \n[ ] [ ] [ ] [ ] \n");
+    board.set_square(0, 0, Stack::Blocked);
+    for column in 1..4 {
+        board.set_square(column, 1, Stack::Height(2));
+    }
+    board.set_square(2, 2, Stack::Height(3));
+    assert_eq!(board.to_string(), "[#] [ ] [ ] [ ] \n[ ] [2] [2] [2] \n[ ] [ ] [3] [ ] \n");
+    assert_eq!(board.row_iterator(1).collect::<Vec<Stack>>(),
+        vec![Stack::Height(0), Stack::Height(2), Stack::Height(2), Stack::Height(2)]);
+    assert_eq!(board.run_through(2, 1, (1, 0)), Line::flat((1, 1), (1, 0), 3));
+    assert_eq!(board.run_through(0, 1, (1, 0)).length, 0);
+    let diagonals = board.diagonal_iterator_iterator(3).unwrap().count();
+    assert_eq!(diagonals, 4);
+    assert_eq!(board.annotated(vec![((3, 2), "*".to_string())], Vec::new()).to_string(),
+        "[#] [ ] [ ] [ ] \n[ ] [2] [2] [2] \n[ ] [ ] [3] [*] \n");
+
+    let hash = board.hash();
+    assert_eq!(hash, board.clone().with_seed(DEFAULT_SEED).hash());
+    board.set_square(2, 2, Stack::Height(2));
+    assert_ne!(board.hash(), hash);
+    board.set_square(2, 2, Stack::Height(4));
+    assert_eq!(board.hash(), hash);
+    board.set_square(2, 2, Stack::Height(2));
+    let (canonical, symmetry) = board.canonical(true);
+    assert_eq!(symmetry, Symmetry::MirrorColumns);
+    assert_eq!(canonical.get_square(3, 0), Some(Stack::Blocked));
+    board.reset();
+    assert_eq!(board.hash(), 0);
+    assert!(board.get_square(0, 0).unwrap().is_empty());
+}
+#[test]
+fn players_take_turns_in_order_with_their_marks() {
+    let players = Players::new(3);
+    assert_eq!(players.count(), 3);
+    assert_eq!((0..4).map(|moves| players.to_move(moves)).collect::<Vec<Player>>(),
+        vec![Player::X, Player::O, Player::new(2), Player::X]);
+    assert_eq!(Players::default(), Players::from_marks("XO").unwrap());
+    assert!(Players::default().is_two_player());
+    assert!(!players.is_two_player());
+
+    let players = Players::from_marks("#@%").unwrap().in_order("%#@").unwrap();
+    assert_eq!(players.order(), &[Player::new(2), Player::X, Player::O]);
+    assert_eq!(players.to_move(0), Player::new(2));
+    assert_eq!(players.mark(Player::O), '@');
+    assert_eq!(players.player_marked('%'), Some(Player::new(2)));
+    assert_eq!(players.player_marked('X'), None);
+    // Swapping who starts makes a two-player game the engines don't know.
+    assert!(!Players::default().in_order("OX").unwrap().is_two_player());
+
+    assert_eq!(Players::from_marks("X"), Err(PlayersError::Count(1)));
+    assert_eq!(Players::from_marks("XOYZW"), Err(PlayersError::Count(5)));
+    assert_eq!(Players::from_marks("XOX"), Err(PlayersError::BadMark('X')));
+    assert_eq!(Players::from_marks("X O"), Err(PlayersError::BadMark(' ')));
+    assert_eq!(Players::new(3).in_order("XO"), Err(PlayersError::BadOrder));
+    assert_eq!(Players::new(3).in_order("XOO"), Err(PlayersError::BadOrder));
+    assert_eq!(Players::new(3).in_order("XOZ"), Err(PlayersError::BadOrder));
+
+    // Boards draw the usual marks; games can draw their own.
+    let mut board = Board::new_rectangle(4, 1);
+    for index in 0..4 {
+        board.set_square(index, 0, Square::Played(Player::new(index)));
+    }
+    assert_eq!(board.to_string(), "[X] [O] [Y] [Z] \n");
+    let players = Players::from_marks("1234").unwrap();
+    assert_eq!(board.annotated(Vec::new(), Vec::new()).with_marks(players.square_marks()).to_string(), "[1] [2] [3] [4] \n");
+}
+#[test]
+fn lines_wrap_around_cylinders_and_tori() {
+    let x = Square::Played(Player::X);
+    let mut board = Board::new_rectangle(4, 3);
+    for column in [3, 0, 1] {
+        board.set_square(column, 0, x);
+    }
+    assert_eq!(board.find_line(3), None);
+    let board = board.with_wrap(Wrap::Cylinder);
+    let line = Line::flat((3, 0), (1, 0), 3).wrapped(4, 3);
+    assert_eq!(board.find_line(3), Some(line));
+    assert_eq!(board.run_through(0, 0, (1, 0)), line);
+    assert_eq!(line.cells(), vec![(3, 0, 0), (0, 0, 0), (1, 0, 0)]);
+    assert_eq!(line.to_string(), "3, 0 to 1, 0");
+
+    // Rows go round once and on to see across the seam; columns and
+    // diagonals end at the top and bottom.
+    assert_eq!(board.row_iterator(0).count(), 7);
+    assert_eq!(board.column_iterator(0).count(), 3);
+    assert_eq!(board.diagonal_iterator(3, true).map(|_| ()).count(), 3);
+    assert_eq!(board.diagonal_iterator_iterator(3).unwrap().count(), 8);
+    assert!(board.diagonal_iterator_iterator(4).is_none());
+    assert_eq!(board.loop_length((1, 0)), Some(4));
+    assert_eq!(board.loop_length((1, 1)), None);
+
+    // On a torus, a diagonal of 4 by 3 goes through all twelve squares
+    // before it's back, and columns come round too.
+    let board = board.with_wrap(Wrap::Torus);
+    assert_eq!(board.loop_length((-1, 1)), Some(12));
+    assert_eq!(board.loop_length((0, 1)), Some(3));
+    assert_eq!(board.diagonal_iterator_iterator(12).unwrap().count(), 2);
+    assert_eq!(board.column_iterator(0).count(), 5);
+    let mut board = Board::new(3).with_wrap(Wrap::Torus);
+    board.set_square(1, 2, x).set_square(1, 0, x);
+    assert_eq!(board.find_line(2), Some(Line::flat((1, 2), (0, 1), 2).wrapped(3, 3)));
+    assert_eq!(board.diagonal_iterator_iterator(3).unwrap().count(), 6);
+    assert_eq!(board.symmetries(false).len(), 8);
+    assert_eq!(board.clone().with_wrap(Wrap::Cylinder).symmetries(false).len(), 2);
+}
+#[test]
+fn lines_that_go_right_round_count_each_square_once() {
+    let x = Square::Played(Player::X);
+    let mut board = Board::new(3).with_wrap(Wrap::Torus);
+    for column in 0..3 {
+        board.set_square(column, 1, x);
+    }
+    // Three in a row, but never four, however often the row goes round.
+    assert_eq!(board.find_line(4), None);
+    let line = Line::flat((0, 1), (1, 0), 3).wrapped(3, 3);
+    assert_eq!(board.find_line(3), Some(line));
+    for column in 0..3 {
+        assert_eq!(board.run_through(column, 1, (1, 0)), line);
+    }
+
+    // A broken diagonal is a whole one on a torus.
+    board.reset();
+    for (column, row) in [(2, 0), (0, 1), (1, 2)] {
+        board.set_square(column, row, x);
+    }
+    let line = Line::flat((2, 0), (1, 1), 3).wrapped(3, 3);
+    assert_eq!(board.find_line(3), Some(line));
+    assert_eq!(board.find_line(4), None);
+    assert_eq!(board.run_through(1, 2, (1, 1)), line);
+    assert_eq!(line.cells(), vec![(2, 0, 0), (0, 1, 0), (1, 2, 0)]);
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::agent::Evaluation;
+use crate::board::{Board, Player, Square};
+use crate::connectn::ConnectN;
+use crate::game::{Game, GameStatus};
+
+// Opening book files are plain text. The first line is a header giving the
+// format version and the board the book was built for:
+//
+//     connectn-book <version> <columns> <rows> <win_length>
+//
+// followed by one line per position: its key, the best column and its value
+// (W<plies>, L<plies>, D or H<score>, as in Evaluation).
+pub const VERSION: u32 = 1;
+const MAGIC: &str = "connectn-book";
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    WrongBoard { expected: (usize, usize, usize), found: (usize, usize, usize) },
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::Io(error) => write!(f, "{}", error),
+            BookError::Format(message) => write!(f, "Malformed book: {}", message),
+            BookError::UnsupportedVersion(version) => write!(f, "Unsupported book version {}", version),
+            BookError::WrongBoard { expected, found } => write!(f,
+                "Book is for {}x{} connect {}, not {}x{} connect {}",
+                found.0, found.1, found.2, expected.0, expected.1, expected.2),
+        }
+    }
+}
+
+impl From<io::Error> for BookError {
+    fn from(error: io::Error) -> BookError {
+        BookError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Book {
+    columns: usize,
+    rows: usize,
+    win_length: usize,
+    entries: HashMap<String, (usize, Evaluation)>,
+}
+
+impl Book {
+    pub fn new(columns: usize, rows: usize, win_length: usize) -> Book {
+        Book { columns, rows, win_length, entries: HashMap::new() }
+    }
+
+    pub fn for_game(game: &ConnectN) -> Book {
+        Book::new(game.board().columns(), game.board().rows(), game.win_length())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Stones column by column from the bottom up, e.g. "x/ox//" on a four
+    // column board. The side to move follows from the stone counts.
+    pub fn key(board: &Board) -> String {
+        let mut key = String::new();
+        for column in 0..board.columns() {
+            if column > 0 {
+                key.push('/');
+            }
+            for row in (0..board.rows()).rev() {
+                match board.get_square(column, row) {
+                    Some(Square::Played(Player::X)) => key.push('x'),
+                    Some(Square::Played(Player::O)) => key.push('o'),
+                    _ => break,
+                }
+            }
+        }
+        key
+    }
+
+    pub fn insert(&mut self, board: &Board, column: usize, evaluation: Evaluation) {
+        self.entries.insert(Book::key(board), (column, evaluation));
+    }
+
+    pub fn lookup(&self, board: &Board) -> Option<(usize, Evaluation)> {
+        self.entries.get(&Book::key(board)).copied()
+    }
+
+    // Every position in progress up to `plies` moves from the start, each
+    // reached once however many move orders lead to it.
+    pub fn positions(columns: usize, rows: usize, win_length: usize, plies: usize) -> Vec<ConnectN> {
+        let mut seen = HashSet::new();
+        let mut frontier = vec![ConnectN::new(columns, rows, win_length)];
+        let mut positions = Vec::new();
+        for ply in 0..=plies {
+            let mut next = Vec::new();
+            for game in frontier {
+                if ply < plies {
+                    let player = game.current_player();
+                    for input in game.legal_moves() {
+                        let mut child = game.clone();
+                        if child.play(player, &input) == Ok(GameStatus::InProgress)
+                            && seen.insert(Book::key(child.board())) {
+                            next.push(child);
+                        }
+                    }
+                }
+                positions.push(game);
+            }
+            frontier = next;
+        }
+        positions
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {} {} {} {}", MAGIC, VERSION, self.columns, self.rows, self.win_length)?;
+        let mut keys: Vec<&String> = self.entries.keys().collect();
+        keys.sort();
+        for key in keys {
+            let (column, evaluation) = self.entries[key];
+            writeln!(writer, "{} {} {}", key, column, encode(evaluation))?;
+        }
+        Ok(())
+    }
+
+    // Reads a book, failing unless it was built for the given board.
+    pub fn read<R: BufRead>(reader: R, columns: usize, rows: usize, win_length: usize) -> Result<Book, BookError> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| BookError::Format("empty file".to_string()))??;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 5 || fields[0] != MAGIC {
+            return Err(BookError::Format(format!("bad header {:?}", header)))
+        }
+        let numbers: Vec<usize> = fields[1..].iter()
+            .map(|field| field.parse().map_err(|_| BookError::Format(format!("bad header {:?}", header))))
+            .collect::<Result<_, _>>()?;
+        if numbers[0] != VERSION as usize {
+            return Err(BookError::UnsupportedVersion(numbers[0] as u32))
+        }
+        let found = (numbers[1], numbers[2], numbers[3]);
+        if found != (columns, rows, win_length) {
+            return Err(BookError::WrongBoard { expected: (columns, rows, win_length), found })
+        }
+
+        let mut book = Book::new(columns, rows, win_length);
+        for line in lines {
+            let line = line?;
+            let mut fields = line.rsplitn(3, ' ');
+            let (evaluation, column, key) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(evaluation), Some(column), Some(key)) => (evaluation, column, key),
+                _ => return Err(BookError::Format(format!("bad entry {:?}", line))),
+            };
+            let stacks: Vec<&str> = key.split('/').collect();
+            let fits = stacks.len() == columns
+                && stacks.iter().all(|stack| stack.len() <= rows && stack.chars().all(|mark| mark == 'x' || mark == 'o'));
+            if !fits {
+                return Err(BookError::Format(format!("bad position in {:?}", line)))
+            }
+            // The solver plays whatever column the book gives, so it has to
+            // be one with room in it.
+            let column = column.parse::<usize>().ok()
+                .filter(|&column| column < columns && stacks[column].len() < rows)
+                .ok_or_else(|| BookError::Format(format!("bad column in {:?}", line)))?;
+            let evaluation = decode(evaluation)
+                .ok_or_else(|| BookError::Format(format!("bad value in {:?}", line)))?;
+            book.entries.insert(key.to_string(), (column, evaluation));
+        }
+        Ok(book)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &Path, game: &ConnectN) -> Result<Book, BookError> {
+        let reader = BufReader::new(File::open(path)?);
+        Book::read(reader, game.board().columns(), game.board().rows(), game.win_length())
+    }
+}
+
+fn encode(evaluation: Evaluation) -> String {
+    match evaluation {
+        Evaluation::Win(distance) => format!("W{}", distance),
+        Evaluation::Draw => "D".to_string(),
+        Evaluation::Loss(distance) => format!("L{}", distance),
+        Evaluation::Heuristic(score) => format!("H{}", score),
+    }
+}
+
+fn decode(text: &str) -> Option<Evaluation> {
+    let (kind, number) = text.split_at(1.min(text.len()));
+    match kind {
+        "W" => number.parse().ok().map(Evaluation::Win),
+        "L" => number.parse().ok().map(Evaluation::Loss),
+        "H" => number.parse().ok().map(Evaluation::Heuristic),
+        "D" if number.is_empty() => Some(Evaluation::Draw),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+use crate::solver::Solver;
+
+#[test]
+fn keys_follow_the_stones() {
+    let mut game = ConnectN::new(4, 3, 3);
+    assert_eq!(Book::key(game.board()), "///");
+    game.play(Player::X, &[1]).unwrap();
+    game.play(Player::O, &[1]).unwrap();
+    game.play(Player::X, &[3]).unwrap();
+    assert_eq!(Book::key(game.board()), "/xo//x");
+}
+#[test]
+fn round_trips_through_text() {
+    let mut book = Book::new(5, 4, 4);
+    let game = ConnectN::new(5, 4, 4);
+    book.insert(game.board(), 2, Evaluation::Draw);
+    let mut child = game.clone();
+    child.play(Player::X, &[2]).unwrap();
+    book.insert(child.board(), 1, Evaluation::Loss(12));
+
+    let mut text = Vec::new();
+    book.write(&mut text).unwrap();
+    let read = Book::read(&text[..], 5, 4, 4).unwrap();
+    assert_eq!(read, book);
+    assert_eq!(read.lookup(child.board()), Some((1, Evaluation::Loss(12))));
+}
+#[test]
+fn rejects_the_wrong_board() {
+    let mut text = Vec::new();
+    Book::new(7, 6, 4).write(&mut text).unwrap();
+    assert!(matches!(Book::read(&text[..], 7, 6, 5),
+        Err(BookError::WrongBoard { found: (7, 6, 4), .. })));
+    assert!(matches!(Book::read(&b"connectn-book 2 7 6 4\n"[..], 7, 6, 4),
+        Err(BookError::UnsupportedVersion(2))));
+    assert!(matches!(Book::read(&b"not a book\n"[..], 7, 6, 4),
+        Err(BookError::Format(_))));
+}
+#[test]
+fn rejects_moves_off_the_board() {
+    let read = |entry: &str| Book::read(format!("connectn-book 1 4 3 3\n{}\n", entry).as_bytes(), 4, 3, 3);
+    assert!(read("/xo//x 3 D").is_ok());
+    // A column past the edge, a full one, and positions of another shape.
+    for entry in ["/xo//x 4 D", "/xox//x 1 D", "/xo/x 3 D", "/xoxo//x 3 D", "/xq//x 3 D"] {
+        assert!(matches!(read(entry), Err(BookError::Format(_))), "{}", entry);
+    }
+}
+#[test]
+fn solver_plays_from_the_book() {
+    let game = ConnectN::new(5, 4, 4);
+    let mut solver = Solver::for_game(&game);
+    let positions = Book::positions(5, 4, 4, 2);
+    // The empty board, five first moves and twenty-five replies.
+    assert_eq!(positions.len(), 1 + 5 + 25);
+
+    let mut book = Book::for_game(&game);
+    for position in &positions {
+        let player = position.current_player();
+        let (column, evaluation) = solver.best_move(position, player).unwrap();
+        book.insert(position.board(), column, evaluation);
+    }
+    let (_, value) = book.lookup(game.board()).unwrap();
+    assert_eq!(value, solver.solve(&game, Player::X));
+
+    // A doctored entry shows the book is consulted before searching.
+    book.insert(game.board(), 4, value);
+    let mut solver = Solver::for_game(&game).with_book(book);
+    assert_eq!(solver.best_move(&game, Player::X), Some((4, value)));
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::board::{Board, BoardIterator, Player, Square};
+use crate::board::{column_name, Board, Line, Player, Players, Square, Wrap, DIRECTIONS};
 
 use crate::game::{
     Game,
@@ -8,78 +8,185 @@ use crate::game::{
     GameError,
 };
 
+// Bitboard copy of a position, for boards that fit in a u128. As in the
+// solver, each column takes rows + 1 bits from the bottom up, and the spare
+// bit on top keeps lines from running on from one column into the next.
+#[derive(Debug, Clone)]
+struct Bitboard {
+    rows: usize,
+    // X's stones, then O's.
+    stones: [u128; 2],
+    heights: Vec<usize>,
+}
+
+impl Bitboard {
+    fn fits(columns: usize, rows: usize) -> bool {
+        columns * (rows + 1) <= 128
+    }
+
+    fn new(columns: usize, rows: usize) -> Bitboard {
+        Bitboard { rows, stones: [0, 0], heights: vec![0; columns] }
+    }
+
+    fn is_full(&self, column: usize) -> bool {
+        self.heights[column] == self.rows
+    }
+
+    // Drops a stone and returns the board row it landed on, counting from
+    // the top like Board does.
+    fn play(&mut self, column: usize, player: Player) -> usize {
+        let height = self.heights[column];
+        self.stones[player.index()] |= 1 << (column * (self.rows + 1) + height);
+        self.heights[column] += 1;
+        self.rows - 1 - height
+    }
+
+    // Whether `player` has `win_length` in a row: shifting the stones one
+    // step along a direction at a time and anding leaves the cells that
+    // start a long enough line.
+    fn has_line(&self, player: Player, win_length: usize) -> bool {
+        let stones = self.stones[player.index()];
+        let height = self.rows + 1;
+        [1, height - 1, height, height + 1].iter().any(|&direction| {
+            let mut line = stones;
+            for step in 1..win_length {
+                line &= stones.checked_shr((step * direction) as u32).unwrap_or(0);
+            }
+            line != 0
+        })
+    }
+}
+
+// The board is always kept, for drawing and for the engines that read it.
+// Two-player games on flat boards small enough for a bitboard keep one too,
+// which answers for the game's status; others are scanned square by square.
+#[derive(Clone)]
 pub struct ConnectN {
     win_length: usize,
     board: Board,
+    bits: Option<Bitboard>,
+    players: Players,
+    stones: usize,
+    // Kept up to date by `play`, which only has to look at the lines
+    // through the stone just dropped.
+    status: GameStatus,
 }
 
 impl ConnectN {
     pub fn new(columns: usize, rows: usize, win_length: usize) -> ConnectN {
-        ConnectN { win_length, board: Board::new_rectangle(columns, rows) }
+        let bits = if Bitboard::fits(columns, rows) { Some(Bitboard::new(columns, rows)) } else { None };
+        ConnectN {
+            win_length,
+            board: Board::new_rectangle(columns, rows),
+            bits,
+            players: Players::default(),
+            stones: 0,
+            status: GameStatus::InProgress,
+        }
     }
 
-    fn incremental_count(current: Square, last: Square, count: usize) -> usize {
-        match current {
-            Square::Empty => 0,
-            Square::Played(_) => {
-                if last == current {
-                    count + 1
-                } else {
-                    1
-                }
-            }
+    // The bitboard only has room for two players.
+    pub fn with_players(mut self, players: Players) -> ConnectN {
+        if players.count() > 2 {
+            self.bits = None;
         }
+        self.players = players;
+        self
     }
 
-    fn detect_winning_line(&self, iterator: &mut BoardIterator, to_win: usize) -> Option<Player> {
-        let mut last = Square::Empty;
-        let mut count = 0;
-        while let Some(square) = iterator.next() {
-            count = Self::incremental_count(square, last, count);
-            if count >= to_win {
-                return square.to_option()
-            }
-            last = square;
+    // Lines may run off one side of the board and on at the other, or also
+    // off the top and on at the bottom. The bitboard's lines can't.
+    pub fn with_wrap(mut self, wrap: Wrap) -> ConnectN {
+        if wrap != Wrap::Flat {
+            self.bits = None;
         }
+        self.board = self.board.with_wrap(wrap);
+        self
+    }
 
-        None
+    // The same game without the bitboard, to check it against.
+    #[cfg(test)]
+    fn scanned(columns: usize, rows: usize, win_length: usize) -> ConnectN {
+        ConnectN { bits: None, ..ConnectN::new(columns, rows, win_length) }
     }
 
-    fn detect_win(&self) -> Option<Player> {
-        let mut diagonal_iterator_iterator = self.board.diagonal_iterator_iterator(self.win_length).unwrap();
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
 
-        while let Some(mut diagonal_iterator) = diagonal_iterator_iterator.next() {
-            if let Some(player) = self.detect_winning_line(&mut diagonal_iterator, self.win_length) {
-                return Some(player);
-            }
-        }
+    pub fn win_length(&self) -> usize {
+        self.win_length
+    }
 
-        for row in 0..self.board.rows() {
-            let mut horizontal_iterator = self.board.row_iterator(row);
-            if let Some(player) = self.detect_winning_line(&mut horizontal_iterator, self.win_length) {
-                return Some(player);
-            }
-        }
+    fn detect_win(&self) -> Option<(Player, Line)> {
+        self.board.find_line(self.win_length)
+            .and_then(|line| self.board.get_square(line.start.0, line.start.1)?.to_option().map(|player| (player, line)))
+    }
 
-        for column in 0..self.board.columns() {
-            let mut vertical_iterator = self.board.column_iterator(column);
-            if let Some(player) = self.detect_winning_line(&mut vertical_iterator, self.win_length) {
-                return Some(player);
-            }
+    // Drops a stone and returns the row it landed on.
+    fn play(&mut self, column: usize, player: Player) -> usize {
+        let row = match &mut self.bits {
+            Some(bits) => bits.play(column, player),
+            None => {
+                let mut row = 0;
+                while self.board.get_square(column, row) == Some(Square::Empty) {
+                    row += 1;
+                }
+                row - 1
+            },
+        };
+
+        self.board.set_square(column, row, Square::Played(player));
+        self.stones += 1;
+        row
+    }
+
+    // The status after `player` dropped a stone on (column, row). A new line
+    // has to run through it, unless someone had already won; then the full
+    // check decides who, as it always has.
+    fn status_after(&self, column: usize, row: usize, player: Player) -> GameStatus {
+        if self.status != GameStatus::InProgress {
+            return self.full_status()
+        }
+        // Taken in the order the scan takes them.
+        let won = DIRECTIONS.iter()
+            .map(|&step| self.board.run_through(column, row, step))
+            .find(|line| line.length >= self.win_length);
+        if let Some(line) = won {
+            GameStatus::Won(player, line)
+        } else if self.is_full() {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
         }
+    }
 
-        None
+    fn is_full(&self) -> bool {
+        match &self.bits {
+            Some(bits) => (0..bits.heights.len()).all(|column| bits.is_full(column)),
+            None => self.detect_draw(),
+        }
     }
 
-    fn play(&mut self, column: usize, player: Player) {
-        let mut row = 0;
-        while self.board.get_square(column, row) == Some(Square::Empty) {
-            //println!("Checking row {}", row);
-            row += 1;
+    // Checks the whole board. The bitboard can rule out a win quickly, but
+    // only the scan says where one is.
+    fn full_status(&self) -> GameStatus {
+        match &self.bits {
+            Some(bits) if !bits.has_line(Player::X, self.win_length) && !bits.has_line(Player::O, self.win_length) => {
+                if self.is_full() { GameStatus::Drawn } else { GameStatus::InProgress }
+            },
+            _ => self.scan_status(),
         }
-        row -= 1;
+    }
 
-        self.board.set_square(column, row, Square::Played(player));
+    fn scan_status(&self) -> GameStatus {
+        if let Some((player, line)) = self.detect_win() {
+            GameStatus::Won(player, line)
+        } else if self.detect_draw() {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
+        }
     }
 
     fn detect_draw(&self) -> bool {
@@ -97,26 +204,21 @@ impl ConnectN {
 impl Game for ConnectN {
 
     fn get_status(&self) -> GameStatus {
-        if let Some(player) = self.detect_win() {
-            GameStatus::Won(player)
-        } else if self.detect_draw() {
-            GameStatus::Drawn
-        } else {
-            GameStatus::InProgress
-        }
+        self.status.clone()
     }
 
-    fn play(&mut self, player: Player, input: &Vec<usize>) -> Result<GameStatus, GameError> {
+    fn play(&mut self, player: Player, input: &[usize]) -> Result<GameStatus, GameError> {
         let column = input[0];
         if column >= self.board.columns() {
             return Err(GameError::OutOfBounds)
         }
 
-        return match self.board.get_square(column, 0) {
+        match self.board.get_square(column, 0) {
             Some(Square::Played(_)) => Err(GameError::SquareNotEmpty),
             Some(Square::Empty) => {
-                self.play(column, player);
-                Ok(self.get_status())
+                let row = self.play(column, player);
+                self.status = self.status_after(column, row, player);
+                Ok(self.status.clone())
             },
             None => Err(GameError::OutOfBounds),
         }
@@ -124,15 +226,199 @@ impl Game for ConnectN {
 
     fn reset(&mut self) {
         self.board.reset();
+        if let Some(bits) = &mut self.bits {
+            *bits = Bitboard::new(bits.heights.len(), bits.rows);
+        }
+        self.stones = 0;
+        self.status = GameStatus::InProgress;
     }
 
     fn num_inputs(&self) -> usize {
-        return 1;
+        1
+    }
+
+    fn annotate(&self, labels: Vec<(Vec<usize>, String)>) -> String {
+        let headers = labels.into_iter().map(|(input, label)| (input[0], label)).collect();
+        self.board.annotated(Vec::new(), headers).with_marks(self.players.square_marks()).to_string()
+    }
+
+    fn legal_moves(&self) -> Vec<Vec<usize>> {
+        (0..self.board.columns())
+            .filter(|&column| match &self.bits {
+                Some(bits) => !bits.is_full(column),
+                None => self.board.get_square(column, 0) == Some(Square::Empty),
+            })
+            .map(|column| vec![column])
+            .collect()
+    }
+
+    fn current_player(&self) -> Player {
+        self.players.to_move(self.stones)
+    }
+
+    fn players(&self) -> &Players {
+        &self.players
+    }
+
+    fn position_hash(&self) -> u64 {
+        self.board.hash() ^ self.board.zobrist().side_to_move(self.current_player())
+    }
+
+    // As connect four games are written down: the column's letter, from a
+    // on the left.
+    fn notation(&self, input: &[usize]) -> String {
+        column_name(input[0])
     }
 }
 
 impl fmt::Display for ConnectN {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.board)
+        write!(f, "{}", self.board.annotated(Vec::new(), Vec::new()).with_marks(self.players.square_marks()))
+    }
+}
+
+#[cfg(test)]
+use crate::rng::Rng;
+
+#[test]
+fn bitboard_agrees_with_the_scan() {
+    let mut rng = Rng::new(1);
+    for &(columns, rows, win_length) in &[(7, 6, 4), (5, 4, 4), (4, 4, 3), (3, 5, 3), (6, 7, 5), (9, 8, 5), (2, 2, 2)] {
+        for _ in 0..50 {
+            let mut fast = ConnectN::new(columns, rows, win_length);
+            let mut scanned = ConnectN::scanned(columns, rows, win_length);
+            assert!(fast.bits.is_some());
+            // Play goes on after a win, and tries full columns and columns
+            // off the board.
+            for _ in 0..2 * columns * rows {
+                let column = rng.below(columns + 1);
+                let player = scanned.current_player();
+                assert_eq!(fast.current_player(), player);
+                assert_eq!(Game::play(&mut fast, player, &[column]), Game::play(&mut scanned, player, &[column]));
+                assert_eq!(fast.get_status(), scanned.get_status(), "\n{}", scanned);
+                assert_eq!(fast.legal_moves(), scanned.legal_moves());
+                assert_eq!(fast.to_string(), scanned.to_string());
+            }
+        }
+    }
+    // Too big for a bitboard.
+    assert!(ConnectN::new(15, 15, 5).bits.is_none());
+}
+#[test]
+fn incremental_status_matches_the_full_scan() {
+    let mut rng = Rng::new(2);
+    for &(columns, rows, win_length) in &[(7, 6, 4), (4, 4, 3), (6, 7, 5), (15, 15, 5), (19, 19, 5), (20, 3, 3)] {
+        for _ in 0..20 {
+            let mut game = ConnectN::new(columns, rows, win_length);
+            // Going on past the end of the game, until the board fills up.
+            while !game.legal_moves().is_empty() {
+                let moves = game.legal_moves();
+                let player = game.current_player();
+                let status = Game::play(&mut game, player, &moves[rng.below(moves.len())]).unwrap();
+                assert_eq!(status, game.scan_status(), "\n{}", game);
+            }
+        }
+    }
+}
+#[test]
+fn wins_say_where_the_line_is() {
+    let mut game = ConnectN::new(7, 6, 4);
+    let mut status = GameStatus::InProgress;
+    // X joins two pairs on the bottom row into five in a row.
+    for column in [0, 0, 1, 1, 3, 3, 4, 4, 2] {
+        let player = game.current_player();
+        status = Game::play(&mut game, player, &[column]).unwrap();
+    }
+    assert_eq!(status, GameStatus::Won(Player::X, Line::flat((0, 5), (1, 0), 5)));
+    assert_eq!(status, game.scan_status());
+
+    let mut game = ConnectN::new(7, 6, 4);
+    for column in [0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3] {
+        let player = game.current_player();
+        status = Game::play(&mut game, player, &[column]).unwrap();
+    }
+    let line = Line::flat((3, 2), (-1, 1), 4);
+    assert_eq!(status, GameStatus::Won(Player::X, line));
+    assert_eq!(line.cells(), vec![(3, 2, 0), (2, 3, 0), (1, 4, 0), (0, 5, 0)]);
+    assert_eq!(line.to_string(), "3, 2 to 0, 5");
+}
+#[test]
+fn three_and_four_players_take_turns_and_win() {
+    let mut rng = Rng::new(3);
+    for count in 2..=Player::MAX {
+        for &(columns, rows, win_length) in &[(7, 6, 4), (9, 7, 4), (5, 5, 3), (8, 4, 3)] {
+            for _ in 0..100 {
+                let mut game = ConnectN::new(columns, rows, win_length).with_players(Players::new(count));
+                assert_eq!(game.bits.is_some(), count == 2);
+                let mut moves = 0;
+                while !game.legal_moves().is_empty() {
+                    let legal = game.legal_moves();
+                    let player = game.current_player();
+                    assert_eq!(player, Player::new(moves % count));
+                    let before = game.get_status();
+                    let status = Game::play(&mut game, player, &legal[rng.below(legal.len())]).unwrap();
+                    moves += 1;
+                    assert_eq!(status, game.scan_status(), "\n{}", game);
+                    if before == GameStatus::InProgress {
+                        if let GameStatus::Won(winner, _) = status {
+                            assert_eq!(winner, player);
+                        }
+                    }
+                }
+            }
+        }
     }
+
+    // Four players, the fourth to move first, dropping into columns 0 to 3
+    // in turn until Z has three down the first column.
+    let players = Players::new(4).in_order("ZXOY").unwrap();
+    let mut game = ConnectN::new(4, 4, 3).with_players(players);
+    let mut status = GameStatus::InProgress;
+    for &column in &[0, 1, 2, 3, 0, 1, 2, 3, 0] {
+        let player = game.current_player();
+        status = Game::play(&mut game, player, &[column]).unwrap();
+    }
+    assert_eq!(status, GameStatus::Won(Player::new(3), Line::flat((0, 1), (0, 1), 3)));
+    assert_eq!(game.to_string(), "[ ] [ ] [ ] [ ] \n[Z] [ ] [ ] [ ] \n[Z] [X] [O] [Y] \n[Z] [X] [O] [Y] \n");
+}
+#[test]
+fn lines_wrap_round_the_sides() {
+    let mut rng = Rng::new(4);
+    for &wrap in &[Wrap::Cylinder, Wrap::Torus] {
+        for &(columns, rows, win_length) in &[(7, 6, 4), (3, 4, 4), (5, 5, 3), (4, 3, 5)] {
+            for _ in 0..50 {
+                let mut game = ConnectN::new(columns, rows, win_length).with_wrap(wrap);
+                assert!(game.bits.is_none());
+                while !game.legal_moves().is_empty() {
+                    let moves = game.legal_moves();
+                    let player = game.current_player();
+                    let status = Game::play(&mut game, player, &moves[rng.below(moves.len())]).unwrap();
+                    assert_eq!(status, game.scan_status(), "\n{}", game);
+                }
+            }
+        }
+    }
+
+    // X's two stones on each side join up.
+    let mut game = ConnectN::new(7, 6, 4).with_wrap(Wrap::Cylinder);
+    let mut status = GameStatus::InProgress;
+    for column in [0, 0, 6, 6, 1, 1, 5] {
+        let player = game.current_player();
+        status = Game::play(&mut game, player, &[column]).unwrap();
+    }
+    assert_eq!(status, GameStatus::Won(Player::X, Line::flat((5, 5), (1, 0), 4).wrapped(7, 6)));
+
+    // Only three columns, so a row of X's three is never four.
+    let mut game = ConnectN::new(3, 4, 4).with_wrap(Wrap::Cylinder);
+    for column in [0, 0, 1, 1, 2] {
+        let player = game.current_player();
+        status = Game::play(&mut game, player, &[column]).unwrap();
+    }
+    assert_eq!(status, GameStatus::InProgress);
+}
+#[test]
+fn moves_are_written_by_column_letter() {
+    let game = ConnectN::new(7, 6, 4);
+    assert_eq!(game.notation(&[0]), "a");
+    assert_eq!(game.notation(&[3]), "d");
 }
@@ -0,0 +1,371 @@
+use std::fmt;
+
+use crate::board::{Board, Cell, Line, Player, Square};
+use crate::rng::Rng;
+use crate::zobrist::DEFAULT_SEED;
+
+// The thirteen ways lines run through a cube, as (column, row, layer)
+// steps: along each axis, along the diagonals of each kind of plane, and
+// along the four diagonals through the middle of the cube.
+pub const DIRECTIONS_3D: [(isize, isize, isize); 13] = [
+    (1, 0, 0), (0, 1, 0), (0, 0, 1),
+    (1, 1, 0), (-1, 1, 0),
+    (1, 0, 1), (-1, 0, 1),
+    (0, 1, 1), (0, -1, 1),
+    (1, 1, 1), (-1, 1, 1), (1, -1, 1), (-1, -1, 1),
+];
+
+// Boards stacked into layers, layer 0 at the bottom. Indexing is
+// [column, row, layer]. Each layer hashes with keys from its own seed, and
+// the cube's hash is theirs together.
+#[derive(Debug, Clone)]
+pub struct Cube<C = Square> {
+    layers: Vec<Board<C>>,
+}
+
+#[derive(Debug)]
+pub struct CubeIterator<'a, C = Square> {
+    cube: &'a Cube<C>,
+    position: (isize, isize, isize),
+    step: (isize, isize, isize),
+}
+
+impl<C: Cell> CubeIterator<'_, C> {
+    // How far each square is from the one before, as (column, row, layer).
+    pub fn step(&self) -> (isize, isize, isize) {
+        self.step
+    }
+
+    // Like `next`, but also says where the square is.
+    pub fn next_with_position(&mut self) -> Option<((usize, usize, usize), C)> {
+        let (column, row, layer) = self.position;
+        if column < 0 || row < 0 || layer < 0 {
+            return None
+        }
+        let position = (column as usize, row as usize, layer as usize);
+        let square = self.cube.get_square(position.0, position.1, position.2)?;
+        self.position = (column + self.step.0, row + self.step.1, layer + self.step.2);
+
+        Some((position, square))
+    }
+}
+
+impl<C: Cell> Iterator for CubeIterator<'_, C> {
+    type Item = C;
+
+    fn next(&mut self) -> Option<C> {
+        self.next_with_position().map(|(_, square)| square)
+    }
+}
+
+// Every line through the cube at least `minimum_size` long, each walked
+// from the end where it comes in. Lines are taken one direction of
+// DIRECTIONS_3D at a time, and within a direction layer by layer from the
+// bottom, in reading order.
+#[derive(Debug)]
+pub struct LineIteratorIterator<'a, C = Square> {
+    cube: &'a Cube<C>,
+    minimum_size: usize,
+    direction: usize,
+    square: usize,
+}
+
+impl<'a, C: Cell> Iterator for LineIteratorIterator<'a, C> {
+    type Item = CubeIterator<'a, C>;
+
+    fn next(&mut self) -> Option<CubeIterator<'a, C>> {
+        let (columns, rows) = (self.cube.columns(), self.cube.rows());
+        let squares = columns * rows * self.cube.layers();
+        while self.direction < DIRECTIONS_3D.len() {
+            let step = DIRECTIONS_3D[self.direction];
+            while self.square < squares {
+                let square = self.square;
+                self.square += 1;
+                let start = (square % columns, square / columns % rows, square / (columns * rows));
+                let before = (start.0 as isize - step.0, start.1 as isize - step.1, start.2 as isize - step.2);
+                if !self.cube.contains(before) && self.cube.line_length(start, step) >= self.minimum_size {
+                    return Some(self.cube.line_iterator(start, step))
+                }
+            }
+            self.direction += 1;
+            self.square = 0;
+        }
+        None
+    }
+}
+
+impl Cube {
+    pub fn new(size: usize) -> Cube {
+        Cube::new_cuboid(size, size, size)
+    }
+
+    pub fn new_cuboid(columns: usize, rows: usize, layers: usize) -> Cube {
+        Cube::with_size(columns, rows, layers)
+    }
+}
+
+impl<C: Cell> Cube<C> {
+    pub fn with_size(columns: usize, rows: usize, layers: usize) -> Cube<C> {
+        let layers = (0..layers).map(|_| Board::with_size(columns, rows)).collect();
+        Cube { layers }.with_seed(DEFAULT_SEED)
+    }
+
+    // The same cube hashed with keys drawn from another seed.
+    pub fn with_seed(mut self, seed: u64) -> Cube<C> {
+        let mut seeds = Rng::new(seed);
+        self.layers = self.layers.into_iter().map(|layer| layer.with_seed(seeds.next_u64())).collect();
+        self
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.layers.iter().fold(0, |hash, layer| hash ^ layer.hash())
+    }
+
+    // Xored into a position's hash to tell whose move it is.
+    pub fn side_to_move(&self, player: Player) -> u64 {
+        self.layers[0].zobrist().side_to_move(player)
+    }
+
+    pub fn columns(&self) -> usize {
+        self.layers[0].columns()
+    }
+
+    pub fn rows(&self) -> usize {
+        self.layers[0].rows()
+    }
+
+    pub fn layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn layer(&self, layer: usize) -> &Board<C> {
+        &self.layers[layer]
+    }
+
+    pub fn get_square(&self, column: usize, row: usize, layer: usize) -> Option<C> {
+        self.layers.get(layer)?.get_square(column, row)
+    }
+
+    pub fn set_square(&mut self, column: usize, row: usize, layer: usize, square: C) -> &mut Self {
+        self.layers[layer].set_square(column, row, square);
+        self
+    }
+
+    fn contains(&self, (column, row, layer): (isize, isize, isize)) -> bool {
+        column >= 0 && row >= 0 && layer >= 0
+            && (column as usize) < self.columns() && (row as usize) < self.rows() && (layer as usize) < self.layers()
+    }
+
+    // How many squares there are from `start` to the edge, going by `step`.
+    fn line_length(&self, start: (usize, usize, usize), step: (isize, isize, isize)) -> usize {
+        self.line_iterator(start, step).count()
+    }
+
+    pub fn line_iterator(&self, start: (usize, usize, usize), step: (isize, isize, isize)) -> CubeIterator<'_, C> {
+        CubeIterator {
+            cube: self,
+            position: (start.0 as isize, start.1 as isize, start.2 as isize),
+            step,
+        }
+    }
+
+    pub fn row_iterator(&self, row: usize, layer: usize) -> CubeIterator<'_, C> {
+        self.line_iterator((0, row, layer), (1, 0, 0))
+    }
+
+    pub fn column_iterator(&self, column: usize, layer: usize) -> CubeIterator<'_, C> {
+        self.line_iterator((column, 0, layer), (0, 1, 0))
+    }
+
+    // Up through the layers, from the bottom.
+    pub fn pillar_iterator(&self, column: usize, row: usize) -> CubeIterator<'_, C> {
+        self.line_iterator((column, row, 0), (0, 0, 1))
+    }
+
+    pub fn line_iterator_iterator(&self, minimum_size: usize) -> LineIteratorIterator<'_, C> {
+        LineIteratorIterator { cube: self, minimum_size, direction: 0, square: 0 }
+    }
+
+    // The longest line of squares holding the same piece as (column, row,
+    // layer) that runs through it with the given step. Its length is zero
+    // if the square is empty.
+    pub fn run_through(&self, column: usize, row: usize, layer: usize, step: (isize, isize, isize)) -> Line {
+        let square = match self.get_square(column, row, layer) {
+            Some(square) if !square.is_empty() => square,
+            _ => return Line::layered((column, row, layer), step, 0),
+        };
+        let matches = |position: (isize, isize, isize)| {
+            self.contains(position) && self.get_square(position.0 as usize, position.1 as usize, position.2 as usize) == Some(square)
+        };
+        let mut start = (column as isize, row as isize, layer as isize);
+        while matches((start.0 - step.0, start.1 - step.1, start.2 - step.2)) {
+            start = (start.0 - step.0, start.1 - step.1, start.2 - step.2);
+        }
+        let mut length = 1;
+        let at = |i: usize| (start.0 + i as isize * step.0, start.1 + i as isize * step.1, start.2 + i as isize * step.2);
+        while matches(at(length)) {
+            length += 1;
+        }
+        Line::layered((start.0 as usize, start.1 as usize, start.2 as usize), step, length)
+    }
+
+    // The first run of at least `length` squares holding the same piece, in
+    // the order of `line_iterator_iterator`, reaching as far as it goes.
+    pub fn find_line(&self, length: usize) -> Option<Line> {
+        for mut iterator in self.line_iterator_iterator(length) {
+            let mut last = C::empty();
+            let mut count = 0;
+            while let Some(((column, row, layer), square)) = iterator.next_with_position() {
+                count = match square {
+                    square if square.is_empty() => 0,
+                    square if square == last => count + 1,
+                    _ => 1,
+                };
+                if count >= length {
+                    return Some(self.run_through(column, row, layer, iterator.step()))
+                }
+                last = square;
+            }
+        }
+        None
+    }
+
+    pub fn reset(&mut self) {
+        for layer in &mut self.layers {
+            layer.reset();
+        }
+    }
+
+    pub fn annotated(&self, cells: Vec<((usize, usize, usize), String)>) -> AnnotatedCube<'_, C> {
+        AnnotatedCube { cube: self, cells, marks: Vec::new() }
+    }
+}
+
+// A cube drawn layer by layer, with labels in some of its empty squares.
+pub struct AnnotatedCube<'a, C = Square> {
+    cube: &'a Cube<C>,
+    cells: Vec<((usize, usize, usize), String)>,
+    marks: Vec<(C, String)>,
+}
+
+impl<C: Cell> AnnotatedCube<'_, C> {
+    pub fn with_marks(mut self, marks: Vec<(C, String)>) -> Self {
+        self.marks = marks;
+        self
+    }
+}
+
+impl<C: Cell> fmt::Display for AnnotatedCube<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, layer) in self.cube.layers.iter().enumerate() {
+            let cells = self.cells.iter()
+                .filter(|((_, _, cell_layer), _)| *cell_layer == index)
+                .map(|((column, row, _), label)| ((*column, *row), label.clone()))
+                .collect();
+            writeln!(f, "Layer {}", index)?;
+            write!(f, "{}", layer.annotated(cells, Vec::new()).with_marks(self.marks.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: Cell> fmt::Display for Cube<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.annotated(Vec::new()))
+    }
+}
+
+#[test]
+fn thirteen_directions() {
+    for (index, &(column, row, layer)) in DIRECTIONS_3D.iter().enumerate() {
+        assert_ne!((column, row, layer), (0, 0, 0));
+        for &other in &DIRECTIONS_3D[..index] {
+            assert_ne!(other, (column, row, layer));
+            assert_ne!(other, (-column, -row, -layer));
+        }
+    }
+}
+#[test]
+fn every_line_through_the_cube() {
+    // Qubic has 76 lines of four, and 3x3x3 has 49 of three.
+    assert_eq!(Cube::new(4).line_iterator_iterator(4).count(), 76);
+    assert_eq!(Cube::new(3).line_iterator_iterator(3).count(), 49);
+    assert_eq!(Cube::new(1).line_iterator_iterator(1).count(), 13);
+
+    // On a cuboid, each line counted once, walked to the far side.
+    let cube = Cube::new_cuboid(4, 3, 2);
+    for minimum_size in 1..=4 {
+        let mut expected = 0;
+        for &step in &DIRECTIONS_3D {
+            for layer in 0..2 {
+                for row in 0..3 {
+                    for column in 0..4 {
+                        let before = (column as isize - step.0, row as isize - step.1, layer as isize - step.2);
+                        if !cube.contains(before) && cube.line_length((column, row, layer), step) >= minimum_size {
+                            expected += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let lines: Vec<CubeIterator> = cube.line_iterator_iterator(minimum_size).collect();
+        assert_eq!(lines.len(), expected);
+        for mut line in lines {
+            let step = line.step();
+            let mut length = 0;
+            let mut last = None;
+            while let Some((position, _)) = line.next_with_position() {
+                length += 1;
+                last = Some(position);
+            }
+            let (column, row, layer) = last.unwrap();
+            assert!(length >= minimum_size);
+            assert!(!cube.contains((column as isize + step.0, row as isize + step.1, layer as isize + step.2)));
+        }
+    }
+    assert_eq!(cube.row_iterator(2, 1).count(), 4);
+    assert_eq!(cube.column_iterator(3, 0).count(), 3);
+    assert_eq!(cube.pillar_iterator(0, 0).count(), 2);
+}
+#[test]
+fn lines_in_any_direction() {
+    let mut cube = Cube::new(3);
+    for i in 0..3 {
+        cube.set_square(2 - i, i, i, Square::Played(Player::O));
+    }
+    cube.set_square(0, 0, 0, Square::Played(Player::X));
+    cube.set_square(0, 0, 1, Square::Played(Player::X));
+    assert_eq!(cube.find_line(3), Some(Line::layered((2, 0, 0), (-1, 1, 1), 3)));
+    assert_eq!(cube.find_line(3).unwrap().to_string(), "2, 0, 0 to 0, 2, 2");
+    assert_eq!(cube.find_line(2), Some(Line::layered((0, 0, 0), (0, 0, 1), 2)));
+    assert_eq!(cube.run_through(1, 1, 1, (1, -1, -1)), Line::layered((0, 2, 2), (1, -1, -1), 3));
+    assert_eq!(cube.run_through(1, 0, 1, (1, 0, 0)).length, 0);
+    cube.set_square(1, 1, 1, Square::Empty);
+    assert_eq!(cube.find_line(3), None);
+}
+#[test]
+fn cubes_are_drawn_layer_by_layer() {
+    let mut cube = Cube::new_cuboid(3, 2, 2);
+    cube.set_square(0, 0, 0, Square::Played(Player::X));
+    cube.set_square(2, 1, 1, Square::Played(Player::O));
+    assert_eq!(cube.to_string(), "Layer 0\n[X] [ ] [ ] \n[ ] [ ] [ ] \nLayer 1\n[ ] [ ] [ ] \n[ ] [ ] [O] \n");
+    assert_eq!(cube.annotated(vec![((1, 0, 1), "W".to_string())]).to_string(),
+        "Layer 0\n[X] [ ] [ ] \n[ ] [ ] [ ] \nLayer 1\n[ ] [W] [ ] \n[ ] [ ] [O] \n");
+}
+#[test]
+fn cube_hashes_follow_the_marks() {
+    let mut cube = Cube::new(3);
+    cube.set_square(1, 1, 1, Square::Played(Player::X));
+    cube.set_square(0, 2, 2, Square::Played(Player::O));
+    let hash = cube.hash();
+    assert_ne!(hash, 0);
+    assert_eq!(cube.clone().with_seed(DEFAULT_SEED).hash(), hash);
+    assert_ne!(cube.clone().with_seed(1).hash(), hash);
+    // The same mark in another layer hashes differently.
+    let mut moved = Cube::new(3);
+    moved.set_square(1, 1, 0, Square::Played(Player::X));
+    moved.set_square(0, 2, 2, Square::Played(Player::O));
+    assert_ne!(moved.hash(), hash);
+    cube.reset();
+    assert_eq!(cube.hash(), 0);
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::agent::{self, Agent, SearchInfo};
+use crate::board::Player;
+use crate::game::Game;
+use crate::lookahead::{self, SystemClock};
+use crate::rng::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Difficulty {
+    Beginner,
+    Casual,
+    Strong,
+    Perfect,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Settings {
+    // How far ahead to look; None hands the move to the game's exact engine.
+    pub max_depth: Option<usize>,
+    // Chance of ignoring the search and playing a random legal move.
+    pub mistake_rate: f64,
+    // Pick at random between equally good moves rather than the first one.
+    pub random_ties: bool,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Beginner,
+        Difficulty::Casual,
+        Difficulty::Strong,
+        Difficulty::Perfect,
+    ];
+
+    pub fn settings(self) -> Settings {
+        match self {
+            Difficulty::Beginner => Settings { max_depth: Some(1), mistake_rate: 0.5, random_ties: true },
+            Difficulty::Casual => Settings { max_depth: Some(2), mistake_rate: 0.2, random_ties: true },
+            Difficulty::Strong => Settings { max_depth: Some(4), mistake_rate: 0.05, random_ties: true },
+            Difficulty::Perfect => Settings { max_depth: None, mistake_rate: 0.0, random_ties: false },
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Casual => "Casual",
+            Difficulty::Strong => "Strong",
+            Difficulty::Perfect => "Perfect",
+        })
+    }
+}
+
+// A computer player held back to a difficulty level. `exact` is the game's
+// strongest engine, used when the level has no depth limit.
+pub struct Leveled<G: Game> {
+    settings: Settings,
+    exact: Box<dyn Agent<G>>,
+    rng: Rng,
+    // How long a depth-limited search may take, if it's timed at all.
+    think_time: Option<Duration>,
+    // Whether the last move was the exact engine's.
+    played_exact: bool,
+    last: Option<SearchInfo>,
+}
+
+impl<G: Game> Leveled<G> {
+    pub fn new(difficulty: Difficulty, exact: Box<dyn Agent<G>>, seed: u64) -> Leveled<G> {
+        Leveled {
+            settings: difficulty.settings(),
+            exact,
+            rng: Rng::new(seed),
+            think_time: None,
+            played_exact: false,
+            last: None,
+        }
+    }
+
+    // Depth-limited levels deepen a ply at a time and play from the deepest
+    // search finished within `think_time`. The exact engine keeps its own
+    // time.
+    pub fn with_think_time(mut self, think_time: Duration) -> Leveled<G> {
+        self.think_time = Some(think_time);
+        self
+    }
+}
+
+impl<G: Game> Agent<G> for Leveled<G> {
+    fn choose_move(&mut self, game: &G, player: Player) -> Vec<usize> {
+        agent::assert_two_player(game);
+        let mut moves = game.legal_moves();
+        self.played_exact = false;
+        self.last = None;
+        if self.rng.chance(self.settings.mistake_rate) {
+            return moves.swap_remove(self.rng.below(moves.len()))
+        }
+
+        let depth = match self.settings.max_depth {
+            None => {
+                self.played_exact = true;
+                return self.exact.choose_move(game, player)
+            },
+            Some(depth) => depth,
+        };
+
+        let (scored, mut info) = lookahead::deepen_moves(game, depth, None, self.think_time, &SystemClock);
+        let best = scored.iter().map(|&(_, score)| score).max()
+            .expect("No moves left to choose from");
+        let mut candidates: Vec<Vec<usize>> = scored.into_iter()
+            .filter(|&(_, score)| score == best)
+            .map(|(input, _)| input)
+            .collect();
+        let index = if self.settings.random_ties { self.rng.below(candidates.len()) } else { 0 };
+        let input = candidates.swap_remove(index);
+        // The expected line follows the first of the tied moves.
+        if info.principal_variation.first() != Some(&input) {
+            info.principal_variation = vec![input.clone()];
+        }
+        self.last = Some(info);
+        input
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        if self.played_exact { self.exact.last_search() } else { self.last.clone() }
+    }
+}
+
+#[cfg(test)]
+use crate::game::GameStatus;
+#[cfg(test)]
+use crate::selfplay::play_match;
+#[cfg(test)]
+use crate::board::Board;
+#[cfg(test)]
+use crate::connectn::ConnectN;
+#[cfg(test)]
+use crate::minimax::Minimax;
+#[cfg(test)]
+use crate::solver::Solver;
+#[cfg(test)]
+use crate::tictactoe::Tictactoe;
+
+#[cfg(test)]
+fn assert_levels_ordered<G: Game>(game: &G, exact: impl Fn() -> Box<dyn Agent<G>>) {
+    for pair in Difficulty::ALL.windows(2) {
+        let mut lower = Leveled::new(pair[0], exact(), 1);
+        let mut higher = Leveled::new(pair[1], exact(), 2);
+        let summary = play_match(game, &mut lower, &mut higher, 200);
+        assert!(summary.losses > summary.wins,
+            "{} won {} and {} won {}", pair[0], summary.wins, pair[1], summary.losses);
+    }
+}
+
+#[test]
+fn tictactoe_levels_are_ordered() {
+    assert_levels_ordered(&Tictactoe::new(Board::new(3)), || Box::new(Minimax::new()));
+}
+#[test]
+fn connectn_levels_are_ordered() {
+    let game = ConnectN::new(5, 4, 4);
+    assert_levels_ordered(&game, || Box::new(Solver::for_game(&game)));
+}
+#[test]
+fn levels_report_their_search() {
+    let mut game = Tictactoe::new(Board::new(3));
+    let mut strong = Leveled::new(Difficulty::Strong, Box::new(Minimax::new()), 3)
+        .with_think_time(Duration::from_secs(60));
+    let mut searched = 0;
+    while game.get_status() == GameStatus::InProgress {
+        let player = game.current_player();
+        let input = strong.choose_move(&game, player);
+        // Mistakes are random moves, with no search behind them.
+        if let Some(info) = strong.last_search() {
+            assert!(info.depth >= 1 && info.depth <= 4, "{}", info);
+            assert!(info.nodes > 0);
+            assert_eq!(info.principal_variation[0], input);
+            searched += 1;
+        }
+        game.play(player, &input).unwrap();
+    }
+    assert!(searched > 0);
+}
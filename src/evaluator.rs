@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use crate::agent;
+use crate::board::{Board, BoardIterator, Player, Square};
+use crate::connectn::ConnectN;
+use crate::lookahead::Heuristic;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weights {
+    // Score for a window of win_length cells holding only one player's
+    // stones, indexed by how many stones it holds.
+    pub windows: Vec<i32>,
+    // Bonus for a cell that would complete a line, depending on whether its
+    // row suits the threatening player. Counting rows from 1 at the bottom,
+    // odd rows suit the first player (X) and even rows the second (O).
+    pub good_threat: i32,
+    pub bad_threat: i32,
+}
+
+impl Weights {
+    pub fn for_win_length(win_length: usize) -> Weights {
+        let windows = (0..win_length)
+            .map(|stones| if stones == 0 { 0 } else { 1 << (2 * (stones - 1)).min(24) })
+            .collect();
+        Weights { windows, good_threat: 100, bad_threat: 30 }
+    }
+}
+
+// Static evaluation for ConnectN positions too big to search to the end.
+// Scores every window of win_length cells along the board's rows, columns
+// and diagonals that only one player can still complete.
+pub struct Evaluator {
+    win_length: usize,
+    weights: Weights,
+}
+
+impl Evaluator {
+    pub fn new(win_length: usize) -> Evaluator {
+        Evaluator { win_length, weights: Weights::for_win_length(win_length) }
+    }
+
+    pub fn with_weights(win_length: usize, weights: Weights) -> Evaluator {
+        assert_eq!(weights.windows.len(), win_length, "Need a weight for each stone count");
+        Evaluator { win_length, weights }
+    }
+
+    pub fn for_game(game: &ConnectN) -> Evaluator {
+        agent::assert_two_player(game);
+        Evaluator::new(game.win_length())
+    }
+
+    // Score of the board for `player`: its windows and threats minus the
+    // opponent's.
+    pub fn evaluate_board(&self, board: &Board, player: Player) -> i32 {
+        let mut score = 0;
+        let mut threats = HashSet::new();
+
+        for row in 0..board.rows() {
+            score += self.score_line(board.row_iterator(row), &mut threats);
+        }
+        for column in 0..board.columns() {
+            score += self.score_line(board.column_iterator(column), &mut threats);
+        }
+        if let Some(diagonals) = board.diagonal_iterator_iterator(self.win_length) {
+            for diagonal in diagonals {
+                score += self.score_line(diagonal, &mut threats);
+            }
+        }
+
+        for &((_, row), owner) in &threats {
+            let height = board.rows() - row;
+            let suits_owner = (height % 2 == 1) == (owner == Player::X);
+            score += sign(owner) * if suits_owner { self.weights.good_threat } else { self.weights.bad_threat };
+        }
+
+        if player == Player::X { score } else { -score }
+    }
+
+    // Window scores along one line, from X's point of view. Cells that would
+    // complete a window are added to `threats`.
+    fn score_line(&self, mut line: BoardIterator<'_>, threats: &mut HashSet<((usize, usize), Player)>) -> i32 {
+        let mut cells = Vec::new();
+        while let Some(cell) = line.next_with_position() {
+            cells.push(cell);
+        }
+        // Lines that wrap round go past their start; only windows starting
+        // on the first time round are different.
+        let windows = match line.loop_length() {
+            Some(squares) if squares < self.win_length => 0,
+            Some(squares) => squares,
+            None => cells.len(),
+        };
+
+        let mut score = 0;
+        for window in cells.windows(self.win_length).take(windows) {
+            let mut x = 0;
+            let mut o = 0;
+            let mut gap = None;
+            for &(position, square) in window {
+                match square {
+                    Square::Played(Player::X) => x += 1,
+                    Square::Played(Player::O) => o += 1,
+                    Square::Played(_) => panic!("The evaluator only scores X and O"),
+                    Square::Empty => gap = Some(position),
+                }
+            }
+            let (owner, stones) = match (x, o) {
+                (0, 0) => continue,
+                (x, 0) => (Player::X, x),
+                (0, o) => (Player::O, o),
+                _ => continue,
+            };
+            score += sign(owner) * self.weights.windows[stones.min(self.win_length - 1)];
+            if stones == self.win_length - 1 {
+                threats.insert((gap.unwrap(), owner));
+            }
+        }
+        score
+    }
+}
+
+fn sign(player: Player) -> i32 {
+    match player {
+        Player::X => 1,
+        Player::O => -1,
+        _ => panic!("The evaluator only scores X and O"),
+    }
+}
+
+impl Heuristic<ConnectN> for Evaluator {
+    fn evaluate(&self, game: &ConnectN, player: Player) -> i32 {
+        agent::assert_two_player(game);
+        self.evaluate_board(game.board(), player)
+    }
+}
+
+#[cfg(test)]
+use crate::board::Players;
+#[cfg(test)]
+use crate::game::Game;
+
+#[cfg(test)]
+fn game_from_moves(columns: usize, rows: usize, win_length: usize, moves: &[usize]) -> ConnectN {
+    let mut game = ConnectN::new(columns, rows, win_length);
+    for &column in moves {
+        let player = game.current_player();
+        game.play(player, &[column]).unwrap();
+    }
+    game
+}
+
+#[test]
+fn empty_board_is_even() {
+    let game = ConnectN::new(9, 8, 5);
+    let evaluator = Evaluator::for_game(&game);
+    assert_eq!(evaluator.evaluate(&game, Player::X), 0);
+    assert_eq!(evaluator.evaluate(&game, Player::O), 0);
+}
+#[test]
+fn centre_is_worth_more_than_edge() {
+    let evaluator = Evaluator::new(4);
+    let centre = game_from_moves(7, 6, 4, &[3]);
+    let edge = game_from_moves(7, 6, 4, &[0]);
+    assert!(evaluator.evaluate(&centre, Player::X) > evaluator.evaluate(&edge, Player::X));
+    assert_eq!(evaluator.evaluate(&centre, Player::O), -evaluator.evaluate(&centre, Player::X));
+}
+#[test]
+fn threat_parity_follows_the_player() {
+    let weights = Weights { windows: vec![0, 0, 0, 0], good_threat: 10, bad_threat: 1 };
+    let evaluator = Evaluator::with_weights(4, weights);
+    // X's bottom row threat is on row 1 from the bottom: odd, good for X.
+    let odd = game_from_moves(7, 6, 4, &[0, 0, 1, 1, 2, 6]);
+    assert_eq!(evaluator.evaluate(&odd, Player::X), 10);
+    // The same threat one row up is even, which suits O better.
+    let even = game_from_moves(7, 6, 4, &[2, 0, 0, 1, 1, 3, 2]);
+    assert_eq!(evaluator.evaluate(&even, Player::X), 1);
+}
+#[test]
+#[should_panic(expected = "only play X against O")]
+fn refuses_a_third_player() {
+    let mut game = ConnectN::new(7, 6, 4).with_players(Players::new(3));
+    for column in [0, 1, 2] {
+        let player = game.current_player();
+        game.play(player, &[column]).unwrap();
+    }
+    Evaluator::new(4).evaluate(&game, Player::X);
+}
@@ -1,25 +1,57 @@
 use std::fmt;
 
 use crate::board::{
+    Line,
     Player,
+    Players,
 };
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+// A won game says where the winning line is. If there are several, it's the
+// first a full scan of the board would come to. Connection games are won by
+// a chain of cells instead, which goes from one edge to the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GameStatus {
-    Won(Player),
+    Won(Player, Line),
+    Connected(Player, Vec<(usize, usize)>),
     Drawn,
     InProgress,
 }
 
+impl GameStatus {
+    pub fn winner(&self) -> Option<Player> {
+        match *self {
+            GameStatus::Won(player, _) | GameStatus::Connected(player, _) => Some(player),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GameError {
     SquareNotEmpty,
     OutOfBounds,
 }
 
-pub trait Game : fmt::Display {
+// Games are cloned freely by the engines to try out moves, so positions
+// should stay cheap to copy.
+pub trait Game : fmt::Display + Clone {
     fn get_status(&self) -> GameStatus;
-    fn play(&mut self, player: Player, input: &Vec<usize>) -> Result<GameStatus, GameError>;
+    fn play(&mut self, player: Player, input: &[usize]) -> Result<GameStatus, GameError>;
     fn reset(&mut self);
     fn num_inputs(&self) -> usize;
+    // Every move `current_player` may make, in the same form as `play` input.
+    fn legal_moves(&self) -> Vec<Vec<usize>>;
+    fn current_player(&self) -> Player;
+    // Who is playing, with their marks and the order they take turns in.
+    fn players(&self) -> &Players;
+    // The position drawn with a label beside each of the given moves.
+    fn annotate(&self, labels: Vec<(Vec<usize>, String)>) -> String;
+    // Zobrist hash of the position and the side to move, the same for the
+    // same position however it was reached.
+    fn position_hash(&self) -> u64;
+    // A move written out the way players read it, for printing lines of
+    // play. By default its inputs separated by commas.
+    fn notation(&self, input: &[usize]) -> String {
+        input.iter().map(usize::to_string).collect::<Vec<String>>().join(",")
+    }
 }
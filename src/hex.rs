@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::board::{Player, Players, Square};
+use crate::hexboard::HexBoard;
+
+use crate::game::{
+    Game,
+    GameStatus,
+    GameError,
+};
+
+// Union-find over the hexagons, with one more node for each edge of the
+// board. Stones of a colour that touch are joined, and so are stones and
+// the edges they lie on, so a player has won once their two edges are.
+#[derive(Debug, Clone)]
+struct Groups {
+    parents: Vec<usize>,
+}
+
+impl Groups {
+    fn new(nodes: usize) -> Groups {
+        Groups { parents: (0..nodes).collect() }
+    }
+
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parents[node] != node {
+            self.parents[node] = self.parents[self.parents[node]];
+            node = self.parents[node];
+        }
+        node
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a] = b;
+    }
+
+    fn joined(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+}
+
+// The connection game. X joins the top edge to the bottom one, O the left
+// edge to the right. Stones stay put and a full board always has a winner,
+// so there are no draws. With the swap rule, O's first move may be to take
+// over X's opening stone, by playing on it: it becomes an O stone on the
+// mirror image hexagon, and X moves again.
+#[derive(Clone)]
+pub struct Hex {
+    board: HexBoard,
+    swap: bool,
+    players: Players,
+    moves: usize,
+    // X's first stone, while O could still swap it.
+    opening: Option<(usize, usize)>,
+    groups: Groups,
+    status: GameStatus,
+}
+
+impl Hex {
+    pub fn new(size: usize) -> Hex {
+        Hex {
+            board: HexBoard::new(size),
+            swap: false,
+            players: Players::default(),
+            moves: 0,
+            opening: None,
+            groups: Groups::new(size * size + 4),
+            status: GameStatus::InProgress,
+        }
+    }
+
+    pub fn with_swap(mut self) -> Hex {
+        self.swap = true;
+        self
+    }
+
+    // Hex is for two; this only changes their marks and who starts.
+    pub fn with_players(mut self, players: Players) -> Hex {
+        assert_eq!(players.count(), 2, "Hex is a two-player game");
+        self.players = players;
+        self
+    }
+
+    pub fn board(&self) -> &HexBoard {
+        &self.board
+    }
+
+    // The union-find nodes for a player's two edges.
+    fn edges(&self, player: Player) -> (usize, usize) {
+        let squares = self.board.size() * self.board.size();
+        match player {
+            Player::X => (squares, squares + 1),
+            _ => (squares + 2, squares + 3),
+        }
+    }
+
+    fn place(&mut self, column: usize, row: usize, player: Player) {
+        let size = self.board.size();
+        self.board.set_square(column, row, Square::Played(player));
+        let node = column * size + row;
+        for (other_column, other_row) in self.board.neighbors(column, row) {
+            if self.board.get_square(other_column, other_row) == Some(Square::Played(player)) {
+                self.groups.join(node, other_column * size + other_row);
+            }
+        }
+        let (first, second) = self.edges(player);
+        let along = if player == Player::X { row } else { column };
+        if along == 0 {
+            self.groups.join(node, first);
+        }
+        if along == size - 1 {
+            self.groups.join(node, second);
+        }
+    }
+
+    fn status_after(&mut self, player: Player) -> GameStatus {
+        let (first, second) = self.edges(player);
+        if self.groups.joined(first, second) {
+            GameStatus::Connected(player, self.chain(player))
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    // The shortest run of `player`'s stones from their first edge to their
+    // second, found breadth first, or nothing if their edges aren't joined.
+    fn chain(&self, player: Player) -> Vec<(usize, usize)> {
+        let size = self.board.size();
+        let index = |(column, row): (usize, usize)| column * size + row;
+        let ours = |(column, row): (usize, usize)| self.board.get_square(column, row) == Some(Square::Played(player));
+        let along = |(column, row): (usize, usize)| if player == Player::X { row } else { column };
+        // The stone each one was reached from. Those on the first edge are
+        // reached from themselves.
+        let mut from = vec![None; size * size];
+        let mut queue = VecDeque::new();
+        for column in 0..size {
+            for row in 0..size {
+                if ours((column, row)) && along((column, row)) == 0 {
+                    from[index((column, row))] = Some((column, row));
+                    queue.push_back((column, row));
+                }
+            }
+        }
+        while let Some(mut cell) = queue.pop_front() {
+            if along(cell) == size - 1 {
+                let mut chain = vec![cell];
+                while let Some(previous) = from[index(cell)].filter(|&previous| previous != cell) {
+                    chain.push(previous);
+                    cell = previous;
+                }
+                chain.reverse();
+                return chain
+            }
+            for next in self.board.neighbors(cell.0, cell.1) {
+                if ours(next) && from[index(next)].is_none() {
+                    from[index(next)] = Some(cell);
+                    queue.push_back(next);
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    // O takes over X's opening stone, reflected so that it does for O's
+    // edges what it did for X's.
+    fn swap_opening(&mut self, (column, row): (usize, usize), player: Player) -> GameStatus {
+        self.board.reset();
+        self.groups = Groups::new(self.groups.parents.len());
+        self.place(row, column, player);
+        self.moves += 1;
+        self.opening = None;
+        self.status_after(player)
+    }
+}
+
+impl Game for Hex {
+
+    fn get_status(&self) -> GameStatus {
+        self.status.clone()
+    }
+
+    fn play(&mut self, player: Player, input: &[usize]) -> Result<GameStatus, GameError> {
+        let (column, row) = (input[0], input[1]);
+        if self.opening == Some((column, row)) && self.moves == 1 {
+            self.status = self.swap_opening((column, row), player);
+            return Ok(self.status.clone())
+        }
+        match self.board.get_square(column, row) {
+            Some(Square::Played(_)) => Err(GameError::SquareNotEmpty),
+            Some(Square::Empty) => {
+                self.place(column, row, player);
+                self.moves += 1;
+                self.opening = if self.swap && self.moves == 1 { Some((column, row)) } else { None };
+                self.status = self.status_after(player);
+                Ok(self.status.clone())
+            },
+            None => Err(GameError::OutOfBounds),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.board.reset();
+        self.groups = Groups::new(self.groups.parents.len());
+        self.moves = 0;
+        self.opening = None;
+        self.status = GameStatus::InProgress;
+    }
+
+    fn num_inputs(&self) -> usize {
+        2
+    }
+
+    fn annotate(&self, labels: Vec<(Vec<usize>, String)>) -> String {
+        let cells = labels.into_iter().map(|(input, label)| ((input[0], input[1]), label)).collect();
+        self.board.annotated(cells).with_marks(self.players.square_marks()).to_string()
+    }
+
+    // The empty hexagons, and X's opening stone while it can be swapped.
+    fn legal_moves(&self) -> Vec<Vec<usize>> {
+        let mut moves = Vec::new();
+        for column in 0..self.board.size() {
+            for row in 0..self.board.size() {
+                if self.board.get_square(column, row) == Some(Square::Empty) || self.opening == Some((column, row)) {
+                    moves.push(vec![column, row]);
+                }
+            }
+        }
+        moves
+    }
+
+    fn current_player(&self) -> Player {
+        self.players.to_move(self.moves)
+    }
+
+    fn players(&self) -> &Players {
+        &self.players
+    }
+
+    fn position_hash(&self) -> u64 {
+        let hash = self.board.hash() ^ self.board.zobrist().side_to_move(self.current_player());
+        // A swappable stone isn't the same position as one that can't be.
+        if self.opening.is_some() { !hash } else { hash }
+    }
+}
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.board.annotated(Vec::new()).with_marks(self.players.square_marks()))
+    }
+}
+
+#[cfg(test)]
+use crate::rng::Rng;
+
+#[cfg(test)]
+fn play_all(game: &mut Hex, moves: &[(usize, usize)]) -> GameStatus {
+    let mut status = game.get_status();
+    for &(column, row) in moves {
+        let player = game.current_player();
+        status = game.play(player, &[column, row]).unwrap();
+    }
+    status
+}
+
+// Whether `player`'s stones join their edges, by flood fill.
+#[cfg(test)]
+fn connected(game: &Hex, player: Player) -> bool {
+    let size = game.board.size();
+    let ours = |(column, row): (usize, usize)| game.board.get_square(column, row) == Some(Square::Played(player));
+    let on_edge = |(column, row): (usize, usize), edge| if player == Player::X { row == edge } else { column == edge };
+    let mut seen = Vec::new();
+    let mut todo: Vec<(usize, usize)> = (0..size).flat_map(|a| (0..size).map(move |b| (a, b)))
+        .filter(|&cell| ours(cell) && on_edge(cell, 0))
+        .collect();
+    while let Some(cell) = todo.pop() {
+        if seen.contains(&cell) {
+            continue
+        }
+        if on_edge(cell, size - 1) {
+            return true
+        }
+        seen.push(cell);
+        todo.extend(game.board.neighbors(cell.0, cell.1).into_iter().filter(|&next| ours(next)));
+    }
+    false
+}
+
+// Whether `chain` is a run of `player`'s stones joining their edges.
+#[cfg(test)]
+fn joins_edges(game: &Hex, player: Player, chain: &[(usize, usize)]) -> bool {
+    let size = game.board.size();
+    let along = |(column, row): (usize, usize)| if player == Player::X { row } else { column };
+    !chain.is_empty()
+        && along(chain[0]) == 0
+        && along(chain[chain.len() - 1]) == size - 1
+        && chain.iter().all(|&(column, row)| game.board.get_square(column, row) == Some(Square::Played(player)))
+        && chain.windows(2).all(|pair| game.board.neighbors(pair[0].0, pair[0].1).contains(&pair[1]))
+}
+
+#[test]
+fn players_join_their_own_edges() {
+    // X straight down a column, while O fills a row that misses the right edge.
+    let mut game = Hex::new(3);
+    let status = play_all(&mut game, &[(1, 0), (0, 0), (1, 1), (0, 1), (1, 2)]);
+    assert_eq!(status, GameStatus::Connected(Player::X, vec![(1, 0), (1, 1), (1, 2)]));
+
+    // O across, stepping up a row on the way, which hexagons allow.
+    let mut game = Hex::new(3);
+    let status = play_all(&mut game, &[(0, 0), (0, 2), (2, 2), (1, 1), (0, 1), (2, 0)]);
+    assert_eq!(status, GameStatus::Connected(Player::O, vec![(0, 2), (1, 1), (2, 0)]));
+
+    // Going down and right isn't touching.
+    let mut game = Hex::new(3);
+    let status = play_all(&mut game, &[(0, 0), (2, 0), (1, 1), (2, 1), (2, 2)]);
+    assert_eq!(status, GameStatus::InProgress);
+    assert_eq!(game.play(Player::O, &[1, 1]), Err(GameError::SquareNotEmpty));
+    assert_eq!(game.play(Player::O, &[3, 1]), Err(GameError::OutOfBounds));
+}
+#[test]
+fn every_game_has_a_winner() {
+    let mut rng = Rng::new(5);
+    for size in 1..=7 {
+        for round in 0..50 {
+            // A swapped opening takes a move without adding a stone.
+            let mut game = if round % 2 == 0 { Hex::new(size) } else { Hex::new(size).with_swap() };
+            while game.get_status() == GameStatus::InProgress {
+                let moves = game.legal_moves();
+                let player = game.current_player();
+                game.play(player, &moves[rng.below(moves.len())]).unwrap();
+                let winner = game.get_status().winner();
+                assert_eq!(winner == Some(player), connected(&game, player), "\n{}", game);
+                assert!(!connected(&game, player.opponent()));
+            }
+            match game.get_status() {
+                GameStatus::Connected(winner, chain) => assert!(joins_edges(&game, winner, &chain), "{:?}\n{}", chain, game),
+                status => panic!("{:?}\n{}", status, game),
+            }
+        }
+    }
+}
+#[test]
+fn swapping_the_opening_stone() {
+    let mut game = Hex::new(4).with_swap();
+    play_all(&mut game, &[(1, 0)]);
+    assert_eq!(game.legal_moves().len(), 16);
+    let before = game.position_hash();
+    assert_eq!(play_all(&mut game, &[(1, 0)]), GameStatus::InProgress);
+    // The stone is O's now, reflected, and X moves again.
+    assert_eq!(game.board().get_square(1, 0), Some(Square::Empty));
+    assert_eq!(game.board().get_square(0, 1), Some(Square::Played(Player::O)));
+    assert_eq!(game.current_player(), Player::X);
+    assert_eq!(game.legal_moves().len(), 15);
+    assert_ne!(game.position_hash(), before);
+    // Only once.
+    play_all(&mut game, &[(2, 2)]);
+    assert_eq!(game.play(Player::O, &[2, 2]), Err(GameError::SquareNotEmpty));
+
+    let mut game = Hex::new(4);
+    play_all(&mut game, &[(1, 0)]);
+    assert_eq!(game.legal_moves().len(), 15);
+    assert_eq!(game.play(Player::O, &[1, 0]), Err(GameError::SquareNotEmpty));
+
+    // The swapped stone, on the right edge now, counts towards O's edges.
+    let mut game = Hex::new(2).with_swap();
+    let status = play_all(&mut game, &[(0, 1), (0, 1), (1, 1), (0, 1)]);
+    assert_eq!(status, GameStatus::Connected(Player::O, vec![(0, 1), (1, 0)]));
+}
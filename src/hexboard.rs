@@ -0,0 +1,169 @@
+use std::fmt;
+
+use crate::board::{Board, Cell, Square};
+use crate::zobrist::Zobrist;
+
+// The six ways from a hexagon to its neighbours, in axial (column, row)
+// coordinates: along its row, then to the two rows below and above.
+pub const HEX_DIRECTIONS: [(isize, isize); 6] = [(1, 0), (-1, 0), (0, 1), (-1, 1), (0, -1), (1, -1)];
+
+// A rhombus of hexagons, `size` on a side, in axial coordinates. Each row
+// sits half a hexagon to the right of the one above, so (column, row)
+// touches (column, row + 1) below right and (column - 1, row + 1) below
+// left.
+#[derive(Debug, Clone)]
+pub struct HexBoard<C = Square> {
+    board: Board<C>,
+}
+
+impl HexBoard {
+    pub fn new(size: usize) -> HexBoard {
+        HexBoard::with_size(size)
+    }
+}
+
+impl<C: Cell> HexBoard<C> {
+    pub fn with_size(size: usize) -> HexBoard<C> {
+        HexBoard { board: Board::with_size(size, size) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.board.columns()
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.board.hash()
+    }
+
+    pub fn zobrist(&self) -> &Zobrist {
+        self.board.zobrist()
+    }
+
+    pub fn get_square(&self, column: usize, row: usize) -> Option<C> {
+        self.board.get_square(column, row)
+    }
+
+    pub fn set_square(&mut self, column: usize, row: usize, square: C) -> &mut Self {
+        self.board.set_square(column, row, square);
+        self
+    }
+
+    // The hexagons touching (column, row), in the order of HEX_DIRECTIONS.
+    pub fn neighbors(&self, column: usize, row: usize) -> Vec<(usize, usize)> {
+        HEX_DIRECTIONS.iter()
+            .map(|&(columns, rows)| (column as isize + columns, row as isize + rows))
+            .filter(|&(column, row)| column >= 0 && row >= 0 && (column as usize) < self.size() && (row as usize) < self.size())
+            .map(|(column, row)| (column as usize, row as usize))
+            .collect()
+    }
+
+    pub fn reset(&mut self) {
+        self.board.reset();
+    }
+
+    pub fn annotated(&self, cells: Vec<((usize, usize), String)>) -> AnnotatedHex<'_, C> {
+        AnnotatedHex { board: self, cells, marks: Vec::new() }
+    }
+}
+
+// A hex board drawn as a skewed grid with its rows and columns numbered,
+// and labels in some of its empty hexagons. Hexagons widen to fit the
+// longest label.
+pub struct AnnotatedHex<'a, C = Square> {
+    board: &'a HexBoard<C>,
+    cells: Vec<((usize, usize), String)>,
+    marks: Vec<(C, String)>,
+}
+
+impl<C: Cell> AnnotatedHex<'_, C> {
+    pub fn with_marks(mut self, marks: Vec<(C, String)>) -> Self {
+        self.marks = marks;
+        self
+    }
+}
+
+impl<C: Cell> fmt::Display for AnnotatedHex<'_, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = self.board.size();
+        let width = self.cells.iter().map(|(_, label)| label)
+            .chain(self.marks.iter().map(|(_, mark)| mark))
+            .map(|label| label.chars().count())
+            .chain(std::iter::once((size - 1).to_string().len()))
+            .max()
+            .unwrap_or(1);
+        let margin = (size - 1).to_string().len();
+        // Half a hexagon, as near as spaces get.
+        let shift = (width + 3) / 2;
+
+        write!(f, "{:margin$} ", "")?;
+        for column in 0..size {
+            write!(f, " {:^width$}  ", column)?;
+        }
+        writeln!(f)?;
+        for row in 0..size {
+            write!(f, "{:>margin$} {:indent$}", row, "", indent = row * shift)?;
+            for column in 0..size {
+                let square = self.board.get_square(column, row).unwrap();
+                let label = self.cells.iter().find(|(cell, _)| *cell == (column, row));
+                match (label, self.marks.iter().find(|(cell, _)| *cell == square)) {
+                    (Some((_, label)), _) if square.is_empty() => write!(f, "[{:^width$}] ", label)?,
+                    (_, Some((_, mark))) => write!(f, "[{:^width$}] ", mark)?,
+                    _ => write!(f, "[{:^width$}] ", square.to_string())?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: Cell> fmt::Display for HexBoard<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.annotated(Vec::new()))
+    }
+}
+
+#[cfg(test)]
+use crate::board::Player;
+
+#[test]
+fn hexagons_have_six_neighbors() {
+    let board = HexBoard::new(3);
+    assert_eq!(board.neighbors(1, 1), vec![(2, 1), (0, 1), (1, 2), (0, 2), (1, 0), (2, 0)]);
+    // Acute corners touch two hexagons, obtuse ones three.
+    assert_eq!(board.neighbors(0, 0), vec![(1, 0), (0, 1)]);
+    assert_eq!(board.neighbors(2, 2), vec![(1, 2), (2, 1)]);
+    assert_eq!(board.neighbors(2, 0), vec![(1, 0), (2, 1), (1, 1)]);
+    assert_eq!(board.neighbors(0, 2), vec![(1, 2), (0, 1), (1, 1)]);
+    // Touching goes both ways.
+    for column in 0..3 {
+        for row in 0..3 {
+            for (other_column, other_row) in board.neighbors(column, row) {
+                assert!(board.neighbors(other_column, other_row).contains(&(column, row)));
+            }
+        }
+    }
+}
+#[test]
+fn hex_boards_are_drawn_skewed() {
+    let mut board = HexBoard::new(3);
+    board.set_square(0, 0, Square::Played(Player::X));
+    board.set_square(1, 2, Square::Played(Player::O));
+    assert_eq!(board.to_string(), concat!(
+        "   0   1   2  \n",
+        "0 [X] [ ] [ ] \n",
+        "1   [ ] [ ] [ ] \n",
+        "2     [ ] [O] [ ] \n",
+    ));
+    let labels = vec![((2, 1), "W1".to_string()), ((0, 0), "no".to_string())];
+    assert_eq!(board.annotated(labels).to_string(), concat!(
+        "   0    1    2   \n",
+        "0 [X ] [  ] [  ] \n",
+        "1   [  ] [  ] [W1] \n",
+        "2     [  ] [O ] [  ] \n",
+    ));
+    let hash = board.hash();
+    board.set_square(1, 2, Square::Empty);
+    assert_eq!(board.hash(), board.zobrist().square(0, 0, Player::X));
+    assert_ne!(board.hash(), hash);
+}
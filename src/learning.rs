@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::agent::{self, Agent};
+use crate::board::{Board, Player, Square};
+use crate::game::{Game, GameStatus};
+use crate::rng::Rng;
+use crate::selfplay::{play_match, Summary};
+use crate::tictactoe::Tictactoe;
+
+// Value tables are plain text, like opening books: a header line
+//
+//     tictactoe-values <version> <board size>
+//
+// then one line per position, its key and the chance X goes on to win from
+// it, counting a draw as half a win.
+pub const VERSION: u32 = 1;
+const MAGIC: &str = "tictactoe-values";
+// What a position nobody has learned anything about is worth.
+const UNKNOWN: f64 = 0.5;
+
+#[derive(Debug)]
+pub enum TableError {
+    Io(io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    WrongSize { expected: usize, found: usize },
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::Io(error) => write!(f, "{}", error),
+            TableError::Format(message) => write!(f, "Malformed value table: {}", message),
+            TableError::UnsupportedVersion(version) => write!(f, "Unsupported value table version {}", version),
+            TableError::WrongSize { expected, found } => write!(f,
+                "Value table is for {}x{} boards, not {}x{}", found, found, expected, expected),
+        }
+    }
+}
+
+impl From<io::Error> for TableError {
+    fn from(error: io::Error) -> TableError {
+        TableError::Io(error)
+    }
+}
+
+// Learned values of Tictactoe positions, from X's point of view.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueTable {
+    size: usize,
+    values: HashMap<String, f64>,
+}
+
+impl ValueTable {
+    pub fn new(size: usize) -> ValueTable {
+        ValueTable { size, values: HashMap::new() }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Squares column by column, e.g. "x.o/.x./..." on a 3x3 board.
+    pub fn key(board: &Board) -> String {
+        let mut key = String::new();
+        for column in 0..board.columns() {
+            if column > 0 {
+                key.push('/');
+            }
+            for row in 0..board.rows() {
+                key.push(match board.get_square(column, row) {
+                    Some(Square::Played(Player::X)) => 'x',
+                    Some(Square::Played(Player::O)) => 'o',
+                    _ => '.',
+                });
+            }
+        }
+        key
+    }
+
+    // Finished games are worth what they scored; anything else what has
+    // been learned about it so far.
+    pub fn value(&self, game: &Tictactoe) -> f64 {
+        match game.get_status() {
+            GameStatus::Won(Player::X, _) | GameStatus::Connected(Player::X, _) => 1.0,
+            GameStatus::Won(..) | GameStatus::Connected(..) => 0.0,
+            GameStatus::Drawn => 0.5,
+            GameStatus::InProgress => self.values.get(&ValueTable::key(game.board())).copied().unwrap_or(UNKNOWN),
+        }
+    }
+
+    // Moves `player` could make, each with the value of the position it
+    // leads to as `player` sees it.
+    fn move_values(&self, game: &Tictactoe, player: Player) -> Vec<(Vec<usize>, f64)> {
+        game.legal_moves().into_iter().map(|input| {
+            let mut child = game.clone();
+            child.play(player, &input).unwrap();
+            let value = self.value(&child);
+            (input, if player == Player::X { value } else { 1.0 - value })
+        }).collect()
+    }
+
+    // The move leading to the best position for `player`, the first one on
+    // ties.
+    pub fn best_move(&self, game: &Tictactoe, player: Player) -> Option<Vec<usize>> {
+        agent::assert_two_player(game);
+        self.move_values(game, player).into_iter().rev()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(input, _)| input)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {} {}", MAGIC, VERSION, self.size)?;
+        let mut keys: Vec<&String> = self.values.keys().collect();
+        keys.sort();
+        for key in keys {
+            writeln!(writer, "{} {}", key, self.values[key])?;
+        }
+        Ok(())
+    }
+
+    // Reads a table, failing unless it was learned on boards of `size`.
+    pub fn read<R: BufRead>(reader: R, size: usize) -> Result<ValueTable, TableError> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| TableError::Format("empty file".to_string()))??;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        let numbers: Option<Vec<usize>> = fields.iter().skip(1).map(|field| field.parse().ok()).collect();
+        let numbers = match numbers {
+            Some(numbers) if fields.len() == 3 && fields[0] == MAGIC => numbers,
+            _ => return Err(TableError::Format(format!("bad header {:?}", header))),
+        };
+        if numbers[0] != VERSION as usize {
+            return Err(TableError::UnsupportedVersion(numbers[0] as u32))
+        }
+        if numbers[1] != size {
+            return Err(TableError::WrongSize { expected: size, found: numbers[1] })
+        }
+
+        let mut table = ValueTable::new(size);
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ')
+                .ok_or_else(|| TableError::Format(format!("bad entry {:?}", line)))?;
+            let columns: Vec<&str> = key.split('/').collect();
+            let fits = columns.len() == size
+                && columns.iter().all(|column| column.len() == size && column.chars().all(|mark| "xo.".contains(mark)));
+            if !fits {
+                return Err(TableError::Format(format!("bad position in {:?}", line)))
+            }
+            // Values are chances, and anything else, NaN above all, would
+            // win every comparison in best_move.
+            let value = value.parse::<f64>().ok()
+                .filter(|value| (0.0..=1.0).contains(value))
+                .ok_or_else(|| TableError::Format(format!("bad value in {:?}", line)))?;
+            table.values.insert(key.to_string(), value);
+        }
+        Ok(table)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &Path, size: usize) -> Result<ValueTable, TableError> {
+        ValueTable::read(BufReader::new(File::open(path)?), size)
+    }
+}
+
+// Learns a ValueTable by TD(0): after every move, the value of the position
+// before it is moved towards the value of the position after it by `alpha`.
+// Learning moves are the best known, except for a random one with chance
+// `epsilon`, so that other moves get tried too.
+pub struct Trainer {
+    table: ValueTable,
+    alpha: f64,
+    epsilon: f64,
+    rng: Rng,
+}
+
+impl Trainer {
+    pub fn new(size: usize, alpha: f64, epsilon: f64, seed: u64) -> Trainer {
+        Trainer { table: ValueTable::new(size), alpha, epsilon, rng: Rng::new(seed) }
+    }
+
+    pub fn table(&self) -> &ValueTable {
+        &self.table
+    }
+
+    pub fn into_table(self) -> ValueTable {
+        self.table
+    }
+
+    // Plays one game and learns from it. With an opponent, the learner
+    // plays `learner` and the opponent the other side; otherwise it plays
+    // both.
+    pub fn play_game(&mut self, mut opponent: Option<&mut dyn Agent<Tictactoe>>, learner: Player) -> GameStatus {
+        let mut game = Tictactoe::new(Board::new(self.table.size));
+        loop {
+            let player = game.current_player();
+            let input = match &mut opponent {
+                Some(opponent) if player != learner => opponent.choose_move(&game, player),
+                _ => {
+                    let mut moves = game.legal_moves();
+                    if self.rng.chance(self.epsilon) {
+                        moves.swap_remove(self.rng.below(moves.len()))
+                    } else {
+                        self.table.best_move(&game, player).unwrap()
+                    }
+                },
+            };
+
+            let before = ValueTable::key(game.board());
+            let value = self.table.value(&game);
+            let status = game.play(player, &input).unwrap();
+            let target = self.table.value(&game);
+            self.table.values.insert(before, value + self.alpha * (target - value));
+            if status != GameStatus::InProgress {
+                return status
+            }
+        }
+    }
+}
+
+// Plays uniformly random legal moves.
+pub struct RandomPlayer {
+    rng: Rng,
+}
+
+impl RandomPlayer {
+    pub fn new(seed: u64) -> RandomPlayer {
+        RandomPlayer { rng: Rng::new(seed) }
+    }
+}
+
+impl<G: Game> Agent<G> for RandomPlayer {
+    fn choose_move(&mut self, game: &G, _player: Player) -> Vec<usize> {
+        let mut moves = game.legal_moves();
+        moves.swap_remove(self.rng.below(moves.len()))
+    }
+}
+
+// Plays the best move according to a learned table.
+pub struct Learned {
+    table: ValueTable,
+}
+
+impl Learned {
+    pub fn new(table: ValueTable) -> Learned {
+        Learned { table }
+    }
+}
+
+impl Agent<Tictactoe> for Learned {
+    fn choose_move(&mut self, game: &Tictactoe, player: Player) -> Vec<usize> {
+        self.table.best_move(game, player).expect("No moves left to choose from")
+    }
+}
+
+// How a table fares against a random player over `games` games, taking
+// turns to start.
+pub fn against_random(table: &ValueTable, games: usize, seed: u64) -> Summary {
+    let game = Tictactoe::new(Board::new(table.size()));
+    play_match(&game, &mut Learned::new(table.clone()), &mut RandomPlayer::new(seed), games)
+}
+
+#[test]
+fn learning_beats_a_random_player() {
+    let mut trainer = Trainer::new(3, 0.2, 0.1, 1);
+    let before = against_random(trainer.table(), 200, 2);
+    for round in 0..10000 {
+        let learner = if round % 2 == 0 { Player::X } else { Player::O };
+        trainer.play_game(None, learner);
+    }
+    let after = against_random(trainer.table(), 200, 2);
+    assert!(after.losses < before.losses && after.wins > before.wins, "{:?} then {:?}", before, after);
+    assert!(after.wins + after.draws > 180, "{:?} then {:?}", before, after);
+}
+#[test]
+fn training_is_repeatable() {
+    let train = |seed| {
+        let mut trainer = Trainer::new(3, 0.2, 0.1, seed);
+        let mut opponent = RandomPlayer::new(seed);
+        for _ in 0..200 {
+            trainer.play_game(Some(&mut opponent), Player::X);
+        }
+        trainer.into_table()
+    };
+    assert_eq!(train(5), train(5));
+    assert_ne!(train(5), train(6));
+}
+#[test]
+fn tables_round_trip_through_text() {
+    let mut trainer = Trainer::new(3, 0.5, 0.3, 3);
+    for _ in 0..20 {
+        trainer.play_game(None, Player::X);
+    }
+    let mut text = Vec::new();
+    trainer.table().write(&mut text).unwrap();
+    let read = ValueTable::read(&text[..], 3).unwrap();
+    // Values are written in full, so nothing is lost.
+    assert_eq!(&read, trainer.table());
+    assert!(matches!(ValueTable::read(&text[..], 4), Err(TableError::WrongSize { expected: 4, found: 3 })));
+    assert!(matches!(ValueTable::read(&b"tictactoe-values 9 3\n"[..], 3), Err(TableError::UnsupportedVersion(9))));
+}
+#[test]
+fn rejects_malformed_entries() {
+    let read = |entry: &str| ValueTable::read(format!("tictactoe-values 1 3\n{}\n", entry).as_bytes(), 3);
+    assert!(read("x.o/.x./... 0.75").is_ok());
+    assert!(read("x.o/.x./... 1").is_ok());
+    // Positions of the wrong shape or with other marks, then values that
+    // aren't chances.
+    for entry in ["x.o/.x. 0.5", "x.o/.x./.... 0.5", "x.o/.x./..z 0.5", "x.o/.x./... 1.5", "x.o/.x./... -0.1", "x.o/.x./... NaN"] {
+        assert!(matches!(read(entry), Err(TableError::Format(_))), "{}", entry);
+    }
+}
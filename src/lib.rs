@@ -0,0 +1,22 @@
+pub mod board;
+pub mod cube;
+pub mod hexboard;
+pub mod game;
+pub mod tictactoe;
+pub mod connectn;
+pub mod tictactoe3d;
+pub mod hex;
+pub mod agent;
+pub mod minimax;
+pub mod solver;
+pub mod rng;
+pub mod zobrist;
+pub mod mcts;
+pub mod lookahead;
+pub mod difficulty;
+pub mod evaluator;
+pub mod book;
+pub mod tablebase;
+pub mod threats;
+pub mod selfplay;
+pub mod learning;
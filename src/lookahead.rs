@@ -0,0 +1,542 @@
+use std::time::{Duration, Instant};
+
+use crate::agent::{self, Advisor, Agent, Evaluation, SearchInfo};
+use crate::board::Player;
+use crate::game::{Game, GameStatus};
+
+// Depth-limited negamax over the Game trait. Positions beyond the horizon
+// are scored by a heuristic if there is one and count as even otherwise.
+// Scores are from the mover's point of view; quicker wins score higher.
+const WIN: i32 = 1_000_000;
+const MAX_HEURISTIC: i32 = WIN / 2;
+
+// A static estimate of a position, positive when `player` is ahead.
+pub trait Heuristic<G: Game> {
+    fn evaluate(&self, game: &G, player: Player) -> i32;
+}
+
+pub fn evaluate_moves<G: Game>(game: &G, depth: usize) -> Vec<(Vec<usize>, i32)> {
+    evaluate_moves_with(game, depth, None)
+}
+
+pub fn evaluate_moves_with<G: Game>(game: &G, depth: usize, heuristic: Option<&dyn Heuristic<G>>) -> Vec<(Vec<usize>, i32)> {
+    agent::assert_two_player(game);
+    let mut search = Search::new(heuristic, &SystemClock);
+    let player = game.current_player();
+    game.legal_moves().into_iter().map(|input| {
+        let score = search.score_move(game, player, &input, depth, -WIN, WIN);
+        (input, score)
+    }).collect()
+}
+
+// Scores every move as evaluate_moves_with does, but a ply deeper at a time
+// up to `max_depth`, until `budget` runs out if there is one. The scores are
+// those of the deepest search that finished; the first ply always does.
+pub fn deepen_moves<G: Game>(
+    game: &G,
+    max_depth: usize,
+    heuristic: Option<&dyn Heuristic<G>>,
+    budget: Option<Duration>,
+    clock: &dyn Clock,
+) -> (Vec<(Vec<usize>, i32)>, SearchInfo) {
+    let (scored, info, _) = deepen(game, max_depth, heuristic, budget, clock);
+    (scored, info)
+}
+
+// deepen_moves, also saying whether the scores are from a search that saw
+// every line to the end.
+fn deepen<G: Game>(
+    game: &G,
+    max_depth: usize,
+    heuristic: Option<&dyn Heuristic<G>>,
+    budget: Option<Duration>,
+    clock: &dyn Clock,
+) -> (Vec<(Vec<usize>, i32)>, SearchInfo, bool) {
+    agent::assert_two_player(game);
+    let start = clock.now();
+    let player = game.current_player();
+    let mut search = Search::new(heuristic, clock);
+    let mut scored = Vec::new();
+    let mut principal_variation = Vec::new();
+    let mut depth = 0;
+    let mut complete = false;
+    while depth < max_depth {
+        search.horizon = false;
+        let mut found = Vec::new();
+        let mut best: Option<(i32, Vec<Vec<usize>>)> = None;
+        for input in game.legal_moves() {
+            let score = search.score_move(game, player, &input, depth + 1, -WIN, WIN);
+            if search.out_of_time {
+                break
+            }
+            if best.as_ref().is_none_or(|&(best_score, _)| score > best_score) {
+                best = Some((score, std::iter::once(input.clone()).chain(search.replies()).collect()));
+            }
+            found.push((input, score));
+        }
+        if search.out_of_time {
+            break
+        }
+        depth += 1;
+        scored = found;
+        principal_variation = best.map(|(_, line)| line).unwrap_or_default();
+        // Every line was played out, so searching deeper changes nothing.
+        complete = !search.horizon;
+        if complete {
+            break
+        }
+        search.deadline = budget.map(|budget| start + budget);
+    }
+    let info = SearchInfo {
+        depth,
+        nodes: search.nodes,
+        elapsed: clock.now() - start,
+        principal_variation,
+        ..SearchInfo::default()
+    };
+    (scored, info, complete)
+}
+
+// Where timed searches get the time from. Tests put in a clock of their own,
+// so they don't depend on how fast the machine is.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// How often, in nodes, to look at the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
+struct Search<'a, G: Game> {
+    heuristic: Option<&'a dyn Heuristic<G>>,
+    clock: &'a dyn Clock,
+    deadline: Option<Instant>,
+    nodes: u64,
+    // Set when some line was cut off by the depth limit rather than played
+    // out, so a deeper search could still change the result.
+    horizon: bool,
+    // Set once the deadline passes. Scores found after that are garbage.
+    out_of_time: bool,
+    // The best line found from the node at each ply, for the principal
+    // variation.
+    lines: Vec<Vec<Vec<usize>>>,
+}
+
+impl<'a, G: Game> Search<'a, G> {
+    fn new(heuristic: Option<&'a dyn Heuristic<G>>, clock: &'a dyn Clock) -> Search<'a, G> {
+        Search { heuristic, clock, deadline: None, nodes: 0, horizon: false, out_of_time: false, lines: Vec::new() }
+    }
+
+    fn score_move(&mut self, game: &G, player: Player, input: &[usize], depth: usize, alpha: i32, beta: i32) -> i32 {
+        let mut child = game.clone();
+        match child.play(player, input) {
+            Ok(status) => -self.negamax(&child, status, depth.saturating_sub(1), 1, -beta, -alpha),
+            Err(_) => -WIN,
+        }
+    }
+
+    // The expected line after a move passed to score_move.
+    fn replies(&self) -> Vec<Vec<usize>> {
+        self.lines.get(1).cloned().unwrap_or_default()
+    }
+
+    fn negamax(&mut self, game: &G, status: GameStatus, depth: usize, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.lines.len() <= ply as usize + 1 {
+            self.lines.resize(ply as usize + 2, Vec::new());
+        }
+        self.lines[ply as usize].clear();
+        if self.nodes.is_multiple_of(CLOCK_INTERVAL) && self.deadline.is_some_and(|deadline| self.clock.now() >= deadline) {
+            self.out_of_time = true;
+        }
+        if self.out_of_time {
+            return 0
+        }
+
+        match status {
+            // The player who just moved won.
+            GameStatus::Won(..) | GameStatus::Connected(..) => return -(WIN - ply),
+            GameStatus::Drawn => return 0,
+            GameStatus::InProgress if depth == 0 => {
+                self.horizon = true;
+                return self.heuristic.map_or(0, |heuristic| {
+                    heuristic.evaluate(game, game.current_player()).clamp(-MAX_HEURISTIC, MAX_HEURISTIC)
+                })
+            },
+            GameStatus::InProgress => (),
+        }
+
+        let player = game.current_player();
+        let mut best = -WIN;
+        for input in game.legal_moves() {
+            let mut child = game.clone();
+            if let Ok(status) = child.play(player, &input) {
+                self.lines[ply as usize + 1].clear();
+                let score = -self.negamax(&child, status, depth - 1, ply + 1, -beta, -alpha);
+                best = best.max(score);
+                if score > alpha {
+                    alpha = score;
+                    let mut line = std::mem::take(&mut self.lines[ply as usize + 1]);
+                    line.insert(0, input);
+                    self.lines[ply as usize] = line;
+                }
+                if alpha >= beta {
+                    break
+                }
+            }
+        }
+        best
+    }
+}
+
+fn evaluation(score: i32) -> Evaluation {
+    if score > MAX_HEURISTIC {
+        Evaluation::Win((WIN - score) as usize)
+    } else if score < -MAX_HEURISTIC {
+        Evaluation::Loss((WIN + score) as usize)
+    } else {
+        Evaluation::Heuristic(score)
+    }
+}
+
+// Fixed-depth lookahead for positions without an exact engine.
+pub struct Lookahead<G: Game> {
+    depth: usize,
+    heuristic: Option<Box<dyn Heuristic<G>>>,
+    last: Option<SearchInfo>,
+}
+
+impl<G: Game> Lookahead<G> {
+    pub fn new(depth: usize) -> Lookahead<G> {
+        assert!(depth > 0, "Lookahead has to look at least one move ahead");
+        Lookahead { depth, heuristic: None, last: None }
+    }
+
+    pub fn with_heuristic(depth: usize, heuristic: Box<dyn Heuristic<G>>) -> Lookahead<G> {
+        Lookahead { heuristic: Some(heuristic), ..Lookahead::new(depth) }
+    }
+
+    // The first of the best moves and its score.
+    fn best(&mut self, game: &G) -> Option<(Vec<usize>, i32)> {
+        agent::assert_two_player(game);
+        let start = Instant::now();
+        let player = game.current_player();
+        let mut search = Search::new(self.heuristic.as_deref(), &SystemClock);
+        let mut best: Option<(Vec<usize>, i32, Vec<Vec<usize>>)> = None;
+        for input in game.legal_moves() {
+            let score = search.score_move(game, player, &input, self.depth, -WIN, WIN);
+            if best.as_ref().is_none_or(|&(_, best_score, _)| score > best_score) {
+                best = Some((input, score, search.replies()));
+            }
+        }
+        let (input, score, replies) = best?;
+        self.last = Some(SearchInfo {
+            depth: self.depth,
+            nodes: search.nodes,
+            elapsed: start.elapsed(),
+            principal_variation: std::iter::once(input.clone()).chain(replies).collect(),
+            ..SearchInfo::default()
+        });
+        Some((input, score))
+    }
+}
+
+impl<G: Game> Agent<G> for Lookahead<G> {
+    fn choose_move(&mut self, game: &G, _player: Player) -> Vec<usize> {
+        let (input, _) = self.best(game).expect("No moves left to choose from");
+        input
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl<G: Game> Advisor<G> for Lookahead<G> {
+    fn advise(&mut self, game: &G, _player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return None
+        }
+        let (input, score) = self.best(game)?;
+        Some((input, evaluation(score)))
+    }
+    fn analyze(&mut self, game: &G, _player: Player) -> Vec<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return Vec::new()
+        }
+        evaluate_moves_with(game, self.depth, self.heuristic.as_deref()).into_iter()
+            .map(|(input, score)| (input, evaluation(score)))
+            .collect()
+    }
+}
+
+// Searches one ply deeper at a time until the time budget runs out, then
+// plays the best move of the deepest search that finished. Each search tries
+// the previous one's best move first, which lets alpha-beta cut more.
+pub struct Deepening<G: Game> {
+    budget: Duration,
+    heuristic: Option<Box<dyn Heuristic<G>>>,
+    clock: Box<dyn Clock>,
+    last: Option<SearchInfo>,
+}
+
+impl<G: Game> Deepening<G> {
+    pub fn new(budget: Duration) -> Deepening<G> {
+        Deepening { budget, heuristic: None, clock: Box::new(SystemClock), last: None }
+    }
+
+    pub fn with_heuristic(budget: Duration, heuristic: Box<dyn Heuristic<G>>) -> Deepening<G> {
+        Deepening { heuristic: Some(heuristic), ..Deepening::new(budget) }
+    }
+
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Deepening<G> {
+        self.clock = clock;
+        self
+    }
+
+    // The best move and its score, and whether the search saw every line
+    // to the end.
+    fn best(&mut self, game: &G) -> Option<(Vec<usize>, i32, bool)> {
+        agent::assert_two_player(game);
+        let start = self.clock.now();
+        let player = game.current_player();
+        let mut moves = game.legal_moves();
+        let mut best = None;
+        let mut depth = 0;
+        // The first ply always finishes, so there's a move to play however
+        // short the budget.
+        let mut search = Search::new(self.heuristic.as_deref(), self.clock.as_ref());
+        let mut principal_variation = Vec::new();
+        loop {
+            search.horizon = false;
+            let mut alpha = -WIN;
+            let mut found: Option<(usize, i32, Vec<Vec<usize>>)> = None;
+            for (index, input) in moves.iter().enumerate() {
+                let score = search.score_move(game, player, input, depth + 1, alpha, WIN);
+                if search.out_of_time {
+                    break
+                }
+                if found.as_ref().is_none_or(|&(_, best_score, _)| score > best_score) {
+                    found = Some((index, score, search.replies()));
+                    alpha = alpha.max(score);
+                }
+            }
+            if search.out_of_time {
+                break
+            }
+            let (index, score, replies) = found?;
+            depth += 1;
+            moves[..=index].rotate_right(1);
+            principal_variation = std::iter::once(moves[0].clone()).chain(replies).collect();
+            best = Some((moves[0].clone(), score, !search.horizon));
+            if !search.horizon || score > MAX_HEURISTIC {
+                break
+            }
+            search.deadline = Some(start + self.budget);
+        }
+        self.last = Some(SearchInfo {
+            depth,
+            nodes: search.nodes,
+            elapsed: self.clock.now() - start,
+            principal_variation,
+            ..SearchInfo::default()
+        });
+        best
+    }
+}
+
+impl<G: Game> Agent<G> for Deepening<G> {
+    fn choose_move(&mut self, game: &G, _player: Player) -> Vec<usize> {
+        let (input, _, _) = self.best(game).expect("No moves left to choose from");
+        input
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl<G: Game> Advisor<G> for Deepening<G> {
+    fn advise(&mut self, game: &G, _player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return None
+        }
+        let (input, score, complete) = self.best(game)?;
+        Some((input, exact_evaluation(score, complete)))
+    }
+
+    // Every move is scored in the one search, within the one budget.
+    fn analyze(&mut self, game: &G, _player: Player) -> Vec<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return Vec::new()
+        }
+        let (scored, info, complete) = deepen(game, usize::MAX, self.heuristic.as_deref(), Some(self.budget), self.clock.as_ref());
+        self.last = Some(info);
+        scored.into_iter()
+            .map(|(input, score)| (input, exact_evaluation(score, complete)))
+            .collect()
+    }
+}
+
+// A search that saw every line to the end knows that what isn't a win or a
+// loss is a draw.
+fn exact_evaluation(score: i32, complete: bool) -> Evaluation {
+    match evaluation(score) {
+        Evaluation::Heuristic(_) if complete => Evaluation::Draw,
+        evaluation => evaluation,
+    }
+}
+
+#[cfg(test)]
+use crate::board::Board;
+#[cfg(test)]
+use crate::connectn::ConnectN;
+#[cfg(test)]
+use crate::evaluator::Evaluator;
+#[cfg(test)]
+use crate::tictactoe::Tictactoe;
+
+// A clock that moves on by `tick` each time it's read.
+#[cfg(test)]
+struct Ticking {
+    start: Instant,
+    reads: std::cell::Cell<u32>,
+    tick: Duration,
+}
+
+#[cfg(test)]
+impl Ticking {
+    fn new(tick: Duration) -> Ticking {
+        Ticking { start: Instant::now(), reads: std::cell::Cell::new(0), tick }
+    }
+}
+
+#[cfg(test)]
+impl Clock for Ticking {
+    fn now(&self) -> Instant {
+        let reads = self.reads.get();
+        self.reads.set(reads + 1);
+        self.start + self.tick * reads
+    }
+}
+
+// So a test can keep hold of the clock it gives away.
+#[cfg(test)]
+impl Clock for std::rc::Rc<Ticking> {
+    fn now(&self) -> Instant {
+        self.as_ref().now()
+    }
+}
+
+#[test]
+fn lookahead_reports_its_search() {
+    let mut game = ConnectN::new(5, 4, 4);
+    for column in [1, 1, 2, 2] {
+        let player = game.current_player();
+        game.play(player, &[column]).unwrap();
+    }
+    let mut lookahead = Lookahead::new(3);
+    let input = lookahead.choose_move(&game, Player::X);
+    let info = lookahead.last_search().unwrap();
+    assert_eq!(info.depth, 3);
+    assert!(info.nodes > 0);
+    // The win it found, to the end.
+    assert_eq!(info.principal_variation[0], input);
+    assert_eq!(info.principal_variation.len(), 3);
+}
+#[test]
+fn deepening_solves_small_games() {
+    let game = Tictactoe::new(Board::new(3));
+    let mut deepening = Deepening::new(Duration::from_secs(60));
+    let (_, evaluation) = deepening.advise(&game, Player::X).unwrap();
+    assert_eq!(evaluation, Evaluation::Draw);
+    // Nothing is left beyond the horizon, so it stopped early.
+    let info = deepening.last_search().unwrap();
+    assert!(info.depth <= 9, "{}", info);
+    assert!(info.elapsed < Duration::from_secs(60));
+
+    let mut won = ConnectN::new(5, 4, 4);
+    for column in [0, 0, 1, 1, 2, 2] {
+        let player = won.current_player();
+        won.play(player, &[column]).unwrap();
+    }
+    let mut deepening = Deepening::new(Duration::from_secs(60));
+    let (input, evaluation) = deepening.advise(&won, Player::X).unwrap();
+    assert_eq!(evaluation, Evaluation::Win(1));
+    assert_eq!(deepening.last_search().unwrap().depth, 1);
+    assert_eq!(input, vec![3]);
+}
+#[test]
+fn deepening_keeps_to_the_budget() {
+    // The clock is checked every CLOCK_INTERVAL nodes after the first ply,
+    // and each check finds another quarter of the budget gone.
+    let mut game = ConnectN::new(9, 8, 5);
+    let budget = Duration::from_millis(50);
+    let tick = budget / 4;
+    let mut deepening = Deepening::with_heuristic(budget, Box::new(Evaluator::for_game(&game)))
+        .with_clock(Box::new(Ticking::new(tick)));
+    for _ in 0..6 {
+        let player = game.current_player();
+        let input = deepening.choose_move(&game, player);
+        let info = deepening.last_search().unwrap();
+        assert!(info.depth >= 1, "{}", info);
+        // Four checks' worth of nodes, and a few more while the abandoned
+        // iteration unwinds.
+        assert!(info.nodes < 5 * CLOCK_INTERVAL, "{}", info);
+        assert!(info.elapsed <= budget + tick, "{}", info);
+        game.play(player, &input).unwrap();
+    }
+
+    // Four times the time goes deeper.
+    let mut longer = Deepening::with_heuristic(budget * 4, Box::new(Evaluator::for_game(&game)))
+        .with_clock(Box::new(Ticking::new(tick)));
+    let player = game.current_player();
+    deepening.choose_move(&game, player);
+    longer.choose_move(&game, player);
+    assert!(longer.last_search().unwrap().depth > deepening.last_search().unwrap().depth);
+}
+#[test]
+fn deepening_analyzes_within_one_budget() {
+    let game = Tictactoe::new(Board::new(3));
+    let analysis = Deepening::new(Duration::from_secs(60)).analyze(&game, Player::X);
+    assert_eq!(analysis.len(), 9);
+    assert!(analysis.iter().all(|&(_, evaluation)| evaluation == Evaluation::Draw), "{:?}", analysis);
+
+    // All the moves share the budget, rather than each having it to itself.
+    let game = ConnectN::new(9, 8, 5);
+    let budget = Duration::from_millis(50);
+    let tick = budget / 4;
+    let clock = std::rc::Rc::new(Ticking::new(tick));
+    let mut deepening = Deepening::with_heuristic(budget, Box::new(Evaluator::for_game(&game)))
+        .with_clock(Box::new(clock.clone()));
+    assert_eq!(deepening.analyze(&game, Player::X).len(), 9);
+    // The budget, and the reads before and after it.
+    assert!(tick * clock.reads.get() <= budget + 2 * tick, "{} reads", clock.reads.get());
+}
+#[test]
+fn deepening_reports_the_expected_line() {
+    let mut game = ConnectN::new(5, 4, 4);
+    for column in [1, 1, 2, 2] {
+        let player = game.current_player();
+        game.play(player, &[column]).unwrap();
+    }
+    // X wins in three plies whatever O does: O can't block both ends.
+    let mut deepening = Deepening::new(Duration::from_secs(60));
+    let input = deepening.choose_move(&game, Player::X);
+    let info = deepening.last_search().unwrap();
+    assert_eq!(info.principal_variation[0], input);
+    assert_eq!(info.principal_variation.len(), 3);
+    assert_eq!(info.hit_rate(), None);
+    for input in &info.principal_variation {
+        let player = game.current_player();
+        game.play(player, input).unwrap();
+    }
+    assert_eq!(game.get_status().winner(), Some(Player::X));
+}
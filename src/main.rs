@@ -1,26 +1,43 @@
 use text_io::scan;
 use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
-mod board;
-mod game;
-mod tictactoe;
-mod connectn;
-
-use board::{
+use tictactoe::board::{
     Board,
     Player,
+    Players,
+    PlayersError,
+    Wrap,
 };
 
-use tictactoe::Tictactoe;
-use connectn::ConnectN;
+use tictactoe::tictactoe::Tictactoe;
+use tictactoe::connectn::ConnectN;
+use tictactoe::tictactoe3d::Tictactoe3d;
+use tictactoe::hex::Hex;
+use tictactoe::agent::{Advisor, Agent, SearchInfo};
+use tictactoe::lookahead::{Deepening, Lookahead};
+use tictactoe::evaluator::Evaluator;
+use tictactoe::minimax::Minimax;
+use tictactoe::tablebase::Tablebase;
+use tictactoe::solver::Solver;
+use tictactoe::book::Book;
+use tictactoe::threats::ThreatGuided;
+use tictactoe::mcts::{Budget, Mcts};
+use tictactoe::rng::Rng;
+use tictactoe::difficulty::{Difficulty, Leveled};
+use tictactoe::learning::{Learned, ValueTable};
 
-use game::{
+use tictactoe::game::{
     Game,
     GameError,
     GameStatus,
 };
 
-fn play_tic_tac_toe() {
+fn play_tic_tac_toe(options: &Options) {
+    if !options.players.is_two_player() || options.wrap != Wrap::Flat {
+        return play_mnk(options)
+    }
     println!("Pick a board size: ");
     let board_size: usize;
     {
@@ -29,67 +46,319 @@ fn play_tic_tac_toe() {
 
     let board = Board::new(board_size);
     let mut game = Tictactoe::new(board);
-    play(&mut game);
+    let learned = options.values.as_ref().and_then(|path| match ValueTable::load(path, board_size) {
+        Ok(table) => Some(Box::new(Learned::new(table)) as Box<dyn Agent<Tictactoe>>),
+        Err(error) => {
+            println!("Not using value table {}: {}", path.display(), error);
+            None
+        },
+    });
+    if board_size <= 4 {
+        let mut tablebase = tictactoe_tablebase(options, board_size);
+        let computer = choose_computer(options, "Tablebase", true, || Box::new(tablebase.clone()), || Box::new(Deepening::new(options.think_time)), learned);
+        play(&mut game, computer, Some(&mut tablebase), options.verbose);
+    } else {
+        let computer = choose_computer(options, "Minimax", false, || Box::new(Minimax::for_board_size(board_size).with_think_time(options.think_time)),
+            || Box::new(Deepening::new(options.think_time)), learned);
+        play(&mut game, computer, Some(&mut Minimax::for_board_size(board_size)), options.verbose);
+    }
+}
+
+// Tablebases up to 3x3 take no time to generate, but 4x4 takes seconds, so
+// that one comes from the file given, if there is one.
+fn tictactoe_tablebase(options: &Options, board_size: usize) -> Tablebase {
+    if board_size < 4 {
+        return Tablebase::generate(board_size)
+    }
+    if let Some(path) = &options.tablebase {
+        match Tablebase::load(path, board_size) {
+            Ok(tablebase) => return tablebase,
+            Err(error) => println!("Not using tablebase {}: {}", path.display(), error),
+        }
+    }
+    println!("Generating tablebase...");
+    Tablebase::generate(board_size)
+}
+
+// Tic-Tac-Toe on any board: for more than two, or in another turn order,
+// which the computer players don't know, or with lines wrapping round,
+// which the tablebase and minimax don't.
+fn play_mnk(options: &Options) {
+    println!("Pick a board size and line length (width height length): ");
+    let cols: usize;
+    let rows: usize;
+    let win_length: usize;
+    {
+        scan!("{} {} {}", cols, rows, win_length);
+    }
+
+    let mut game = Tictactoe::mnk(cols, rows, win_length).with_wrap(options.wrap).with_players(options.players.clone());
+    if !options.players.is_two_player() {
+        return play(&mut game, None, None, options.verbose)
+    }
+    let computer = choose_computer(options, "Iterative deepening", false, || Box::new(Deepening::new(options.think_time)),
+        || Box::new(Deepening::new(options.think_time)), None);
+    play(&mut game, computer, Some(&mut Lookahead::new(2)), options.verbose);
+}
+
+// `exact` is the strongest engine the game has, and only gets called Perfect
+// in the menu if it is.
+fn choose_computer<G: Game + 'static>(
+    options: &Options,
+    exact_name: &str,
+    perfect: bool,
+    exact: impl FnOnce() -> Box<dyn Agent<G>>,
+    timed: impl FnOnce() -> Box<dyn Agent<G>>,
+    learned: Option<Box<dyn Agent<G>>>,
+) -> Option<(Player, Box<dyn Agent<G>>)> {
+    println!("Pick opponents. 1: Two players. 2: Computer plays {}. 3: Computer plays {}.",
+        options.players.mark(Player::X), options.players.mark(Player::O));
+    let choice: usize;
+    {
+        scan!("{}", choice);
+    }
+
+    let player = match choice {
+        1 => return None,
+        2 => Player::X,
+        3 => Player::O,
+        _ => panic!("Invalid choice"),
+    };
+
+    println!("Pick a computer player. 1: Beginner. 2: Casual. 3: Strong. 4: {} ({}). 5: Monte Carlo tree search. \
+        6: Iterative deepening.{}", if perfect { "Perfect" } else { "Strongest" }, exact_name,
+        if learned.is_some() { " 7: Learned." } else { "" });
+    let engine: usize;
+    {
+        scan!("{}", engine);
+    }
+
+    let seed = Rng::from_time().next_u64();
+    let agent: Box<dyn Agent<G>> = match (engine, learned) {
+        (1..=4, _) => Box::new(Leveled::new(Difficulty::ALL[engine - 1], exact(), seed).with_think_time(options.think_time)),
+        (5, _) => Box::new(Mcts::new(Budget::Time(options.think_time), seed)),
+        (6, _) => timed(),
+        (7, Some(learned)) => learned,
+        _ => panic!("Invalid choice"),
+    };
+    Some((player, agent))
 }
 
-fn play_connect_n() {
-    println!("Pick a board size (width height): ");
+fn play_connect_n(options: &Options) {
+    println!("Pick a board size and line length (width height length): ");
     let cols: usize;
     let rows: usize;
+    let win_length: usize;
+    {
+        scan!("{} {} {}", cols, rows, win_length);
+    }
+
+    let mut game = ConnectN::new(cols, rows, win_length).with_wrap(options.wrap).with_players(options.players.clone());
+    if !options.players.is_two_player() {
+        return play(&mut game, None, None, options.verbose)
+    }
+    let book = options.book.as_ref().and_then(|path| match Book::load(path, &game) {
+        Ok(book) => Some(book),
+        Err(error) => {
+            println!("Not using opening book {}: {}", path.display(), error);
+            None
+        },
+    });
+    // The solver's lines stop at the edges.
+    let solvable = Solver::fits(cols, rows) && options.wrap == Wrap::Flat;
+    let exact_name = if solvable { "Solver" } else { "Iterative deepening with threat search" };
+    let computer = choose_computer(options, exact_name, solvable, || {
+        if solvable {
+            Box::new(connect_n_solver(&game, &book, options.threads).with_variation(options.verbose))
+        } else {
+            Box::new(connect_n_deepening(&game, options.think_time))
+        }
+    }, || Box::new(Deepening::with_heuristic(options.think_time, Box::new(Evaluator::for_game(&game)))), None);
+    let mut advisor: Box<dyn Advisor<ConnectN>> = if solvable {
+        Box::new(connect_n_solver(&game, &book, options.threads))
+    } else {
+        Box::new(connect_n_deepening(&game, options.think_time))
+    };
+    play(&mut game, computer, Some(advisor.as_mut()), options.verbose);
+}
+
+fn play_tic_tac_toe_3d(options: &Options) {
+    println!("Pick a cube size and line length (size length): ");
+    let size: usize;
+    let win_length: usize;
+    {
+        scan!("{} {}", size, win_length);
+    }
+    println!("Pick how stones are placed. 1: Anywhere. 2: Dropping to the bottom layer.");
+    let placement: usize;
+    {
+        scan!("{}", placement);
+    }
+
+    let game = Tictactoe3d::new(size, size, size, win_length).with_players(options.players.clone());
+    let mut game = match placement {
+        1 => game,
+        2 => game.with_gravity(),
+        _ => panic!("Invalid choice"),
+    };
+    if !options.players.is_two_player() {
+        return play(&mut game, None, None, options.verbose)
+    }
+    let computer = choose_computer(options, "Iterative deepening", false, || Box::new(Deepening::new(options.think_time)),
+        || Box::new(Deepening::new(options.think_time)), None);
+    play(&mut game, computer, Some(&mut Lookahead::new(2)), options.verbose);
+}
+
+fn play_hex(options: &Options) {
+    if options.players.count() != 2 {
+        println!("Hex is for two players.");
+        return
+    }
+    println!("Pick a board size: ");
+    let size: usize;
+    {
+        scan!("{}", size);
+    }
+    println!("Pick a rule for the first move. 1: None. 2: Swap, so the second player may take it over.");
+    let rule: usize;
     {
-        scan!("{} {}", cols, rows);
+        scan!("{}", rule);
     }
 
-    let mut game = ConnectN::new(cols, rows, 4);
-    play(&mut game);
+    let game = Hex::new(size).with_players(options.players.clone());
+    let mut game = match rule {
+        1 => game,
+        2 => game.with_swap(),
+        _ => panic!("Invalid choice"),
+    };
+    println!("{} joins the top and bottom edges, {} the left and right.",
+        options.players.mark(Player::X), options.players.mark(Player::O));
+    if !options.players.is_two_player() {
+        return play(&mut game, None, None, options.verbose)
+    }
+    let think_time = options.think_time;
+    let computer = choose_computer(options, "Monte Carlo tree search", false,
+        || Box::new(Mcts::new(Budget::Time(think_time), Rng::from_time().next_u64())),
+        || Box::new(Deepening::new(think_time)), None);
+    play(&mut game, computer, Some(&mut Lookahead::new(2)), options.verbose);
 }
 
-fn get_input(length: usize) -> Vec<usize> {
-    let row: usize;
-    let column: usize;
+fn connect_n_solver(game: &ConnectN, book: &Option<Book>, threads: usize) -> Solver {
+    let solver = Solver::for_game(game).with_threads(threads);
+    match book {
+        Some(book) => solver.with_book(book.clone()),
+        None => solver,
+    }
+}
+
+// Boards too big for the solver fall back on a heuristic search, which
+// leaves forced wins and their defence to the threat search. Each gets half
+// the think time.
+fn connect_n_deepening(game: &ConnectN, think_time: Duration) -> ThreatGuided<Deepening<ConnectN>> {
+    ThreatGuided::new(4, Deepening::with_heuristic(think_time / 2, Box::new(Evaluator::for_game(game))))
+        .with_think_time(think_time / 2)
+}
+
+enum Command {
+    Move(Vec<usize>),
+    Hint,
+    Analyze,
+}
 
-    match length {
-        2 => {
-            scan!("{} {}", column, row);
-            println!("You played column {}, row {}", column, row);
-            return vec![column, row];
+fn get_input(length: usize) -> Command {
+    loop {
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            std::process::exit(0)
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            continue
+        }
+        if line == "hint" {
+            return Command::Hint
         }
-        1 => {
-            scan!("{}", column);
-            println!("You played column {}", column);
-            return vec![column];
+        if line == "analyze" {
+            return Command::Analyze
+        }
+
+        let input: Result<Vec<usize>, _> = line.split_whitespace().map(str::parse).collect();
+        match (length, input) {
+            (2, Ok(input)) if input.len() == 2 => {
+                println!("You played column {}, row {}", input[0], input[1]);
+                return Command::Move(input)
+            }
+            (1, Ok(input)) if input.len() == 1 => {
+                println!("You played column {}", input[0]);
+                return Command::Move(input)
+            }
+            (3, Ok(input)) if input.len() == 3 => {
+                println!("You played column {}, row {}, layer {}", input[0], input[1], input[2]);
+                return Command::Move(input)
+            }
+            _ => println!("Enter {} numbers, hint or analyze.", length),
         }
-        _ => unimplemented!("Multidimensional games not supported.")
     }
 }
 
-fn play(game: &mut dyn Game) {
-    let mut player = Player::X;
+fn play<G: Game>(game: &mut G, mut computer: Option<(Player, Box<dyn Agent<G>>)>, mut advisor: Option<&mut dyn Advisor<G>>, verbose: bool) {
     let num_inputs = game.num_inputs();
     loop {
         print!("{}", game);
 
-        let input = get_input(num_inputs);
-        match game.play(player, &input) {
-            Ok(GameStatus::InProgress) => {
-                player = match player {
-                    Player::X => Player::O,
-                    Player::O => Player::X
-                };
+        let player = game.current_player();
+        let input = match &mut computer {
+            Some((computer_player, agent)) if *computer_player == player => {
+                let input = agent.choose_move(game, player);
+                match agent.last_search() {
+                    Some(info) => {
+                        println!("Computer played {} ({})", join(", ", input.clone()), info);
+                        if verbose {
+                            print_search(game, &info);
+                        }
+                    },
+                    None => println!("Computer played {}", join(", ", input.clone())),
+                }
+                input
             },
+            _ => match get_input(num_inputs) {
+                Command::Move(input) => input,
+                Command::Hint | Command::Analyze if advisor.is_none() => {
+                    println!("Hints are only for two-player games.");
+                    continue
+                },
+                Command::Hint => {
+                    if let Some((input, evaluation)) = advisor.as_mut().unwrap().advise(game, player) {
+                        println!("Hint: play {} ({})", join(", ", input), evaluation);
+                    }
+                    continue
+                },
+                Command::Analyze => {
+                    let labels = advisor.as_mut().unwrap().analyze(game, player).into_iter()
+                        .map(|(input, evaluation)| (input, evaluation.label()))
+                        .collect();
+                    print!("{}", game.annotate(labels));
+                    continue
+                },
+            },
+        };
+        match game.play(player, &input) {
+            Ok(GameStatus::InProgress) => (),
             Ok(GameStatus::Drawn) => {
                 print!("{}", game);
                 println!("A draw! Try again.");
                 game.reset();
-                player = Player::X;
                 continue
             },
-            Ok(GameStatus::Won(winner)) => {
+            Ok(GameStatus::Won(winner, line)) => {
+                print!("{}", game);
+                println!("{} has won, from {}!", game.players().mark(winner), line);
+                std::process::exit(0)
+            },
+            Ok(GameStatus::Connected(winner, chain)) => {
                 print!("{}", game);
-                println!("{} has won!", match winner {
-                    Player::X => "X",
-                    Player::O => "O"
-                });
+                let cells: Vec<String> = chain.iter().map(|&(column, row)| format!("{}, {}", column, row)).collect();
+                println!("{} has won, along {}!", game.players().mark(winner), cells.join(" to "));
                 std::process::exit(0)
             },
             Err(GameError::SquareNotEmpty) =>
@@ -100,20 +369,123 @@ fn play(game: &mut dyn Game) {
     }
 }
 
+// The expected line and table use of a computer player's search.
+fn print_search<G: Game>(game: &G, info: &SearchInfo) {
+    let line: Vec<String> = info.principal_variation.iter().map(|input| game.notation(input)).collect();
+    println!("Expected line: {}", line.join(" "));
+    match info.hit_rate() {
+        Some(rate) => println!("Table hits: {:.1}% of {} probes", 100.0 * rate, info.table_probes),
+        None => println!("Table hits: no table"),
+    }
+}
+
 fn join<T: fmt::Display>(separator: &str, vec: Vec<T>) -> String {
     vec.iter().map(T::to_string).collect::<Vec<String>>().join(separator)
 }
 
+struct Options {
+    book: Option<PathBuf>,
+    // A value table from the trainer, to play Tictactoe with.
+    values: Option<PathBuf>,
+    // A saved 4x4 Tictactoe tablebase, so it needn't be generated again.
+    tablebase: Option<PathBuf>,
+    // How long timed computer players may think about each move.
+    think_time: Duration,
+    // Threads for the ConnectN solver.
+    threads: usize,
+    // Print the expected line and search statistics after computer moves.
+    verbose: bool,
+    // Marks and turn order. Computer players only play X and O, X first.
+    players: Players,
+    // Which edges lines wrap round, in ConnectN and Tic-Tac-Toe.
+    wrap: Wrap,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        book: None,
+        values: None,
+        tablebase: None,
+        think_time: Duration::from_secs(1),
+        threads: 1,
+        verbose: false,
+        players: Players::default(),
+        wrap: Wrap::Flat,
+    };
+    let mut order = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--book" => options.book = args.next().map(PathBuf::from),
+            "--verbose" => options.verbose = true,
+            "--values" => options.values = args.next().map(PathBuf::from),
+            "--tablebase" => options.tablebase = args.next().map(PathBuf::from),
+            "--think-time" => match args.next().as_deref().and_then(parse_duration) {
+                Some(think_time) => options.think_time = think_time,
+                None => usage(),
+            },
+            "--threads" => match args.next().and_then(|threads| threads.parse().ok()) {
+                Some(threads) if threads > 0 => options.threads = threads,
+                _ => usage(),
+            },
+            "--players" => match args.next().map(|marks| Players::from_marks(&marks)) {
+                Some(Ok(players)) => options.players = players,
+                Some(Err(error)) => bad_players(error),
+                None => usage(),
+            },
+            "--order" => match args.next() {
+                Some(marks) => order = Some(marks),
+                None => usage(),
+            },
+            "--wrap" => match args.next().as_deref() {
+                Some("cylinder") => options.wrap = Wrap::Cylinder,
+                Some("torus") => options.wrap = Wrap::Torus,
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    if let Some(order) = order {
+        options.players = options.players.in_order(&order).unwrap_or_else(|error| bad_players(error));
+    }
+    options
+}
+
+fn bad_players(error: PlayersError) -> ! {
+    eprintln!("{}", error);
+    usage()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: tictactoe [--book <ConnectN opening book>] [--values <Tictactoe value table>] [--tablebase <Tictactoe tablebase>] \
+        [--think-time <time, e.g. 500ms or 2s>] [--threads <ConnectN solver threads>] [--verbose] [--players <2 to 4 marks, e.g. XOYZ>] \
+        [--order <the marks in turn order>] [--wrap <cylinder or torus>]");
+    std::process::exit(2)
+}
+
+// Parses times like "500ms", "2s" or "1.5s".
+fn parse_duration(text: &str) -> Option<Duration> {
+    if let Some(millis) = text.strip_suffix("ms") {
+        millis.parse().ok().map(Duration::from_millis)
+    } else {
+        let seconds: f64 = text.strip_suffix('s')?.parse().ok()?;
+        Duration::try_from_secs_f64(seconds).ok()
+    }
+}
+
 fn main() {
-    println!("Pick a game. 1: Tic-Tac-Toe. 2: ConnectN.");
+    let options = parse_options();
+    println!("Pick a game. 1: Tic-Tac-Toe. 2: ConnectN. 3: 3D Tic-Tac-Toe. 4: Hex.");
     let game_choice: usize;
     {
         scan!("{}", game_choice);
     }
 
     match game_choice {
-        1 => play_tic_tac_toe(),
-        2 => play_connect_n(),
+        1 => play_tic_tac_toe(&options),
+        2 => play_connect_n(&options),
+        3 => play_tic_tac_toe_3d(&options),
+        4 => play_hex(&options),
         _ => panic!("Invalid choice"),
     }
 }
@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+
+use crate::agent::{self, Agent, SearchInfo};
+use crate::board::Player;
+use crate::game::{Game, GameStatus};
+use crate::rng::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Budget {
+    Iterations(usize),
+    Time(Duration),
+}
+
+struct Node {
+    input: Option<Vec<usize>>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<Vec<usize>>,
+    // The player whose move led here; `reward` is from their point of view.
+    player: Player,
+    visits: u32,
+    reward: f64,
+}
+
+// Monte Carlo tree search with UCT selection and uniformly random playouts.
+// It only uses the Game trait, so it plays anything that implements it.
+pub struct Mcts {
+    budget: Budget,
+    exploration: f64,
+    rng: Rng,
+    last: Option<SearchInfo>,
+}
+
+impl Mcts {
+    // Timed searches always run at least once; counted ones have to be asked
+    // to.
+    pub fn new(budget: Budget, seed: u64) -> Mcts {
+        assert!(budget != Budget::Iterations(0), "Monte Carlo tree search needs at least one iteration");
+        Mcts { budget, exploration: std::f64::consts::SQRT_2, rng: Rng::new(seed), last: None }
+    }
+
+    pub fn best_move<G: Game>(&mut self, game: &G) -> Option<Vec<usize>> {
+        agent::assert_two_player(game);
+        self.last = None;
+        if game.get_status() != GameStatus::InProgress {
+            return None
+        }
+        let mut moves = game.legal_moves();
+        if moves.len() <= 1 {
+            return moves.pop()
+        }
+
+        let mut nodes = vec![Node {
+            input: None,
+            parent: None,
+            children: Vec::new(),
+            untried: moves,
+            player: game.current_player().opponent(),
+            visits: 0,
+            reward: 0.0,
+        }];
+
+        let start = Instant::now();
+        let mut iterations = 0;
+        while match self.budget {
+            Budget::Iterations(limit) => iterations < limit,
+            Budget::Time(limit) => iterations == 0 || start.elapsed() < limit,
+        } {
+            self.iterate(&mut nodes, game);
+            iterations += 1;
+        }
+
+        // The expected line follows the most visited child down the tree.
+        let mut line = Vec::new();
+        let mut node = 0;
+        while let Some(&child) = nodes[node].children.iter().max_by_key(|&&child| nodes[child].visits) {
+            line.extend(nodes[child].input.clone());
+            node = child;
+        }
+        self.last = Some(SearchInfo {
+            depth: line.len(),
+            nodes: nodes.len() as u64,
+            elapsed: start.elapsed(),
+            principal_variation: line.clone(),
+            ..SearchInfo::default()
+        });
+        line.into_iter().next()
+    }
+
+    fn iterate<G: Game>(&mut self, nodes: &mut Vec<Node>, root: &G) {
+        let mut game = root.clone();
+        let mut status = GameStatus::InProgress;
+        let mut current = 0;
+
+        // Selection: descend through fully expanded nodes.
+        while nodes[current].untried.is_empty() && !nodes[current].children.is_empty() {
+            current = self.select_child(nodes, current);
+            let player = nodes[current].player;
+            status = game.play(player, nodes[current].input.as_ref().unwrap())
+                .expect("Search tree holds an illegal move");
+        }
+
+        // Expansion: add one untried move.
+        if status == GameStatus::InProgress && !nodes[current].untried.is_empty() {
+            let index = self.rng.below(nodes[current].untried.len());
+            let input = nodes[current].untried.swap_remove(index);
+            let player = game.current_player();
+            status = game.play(player, &input).expect("Legal move was rejected");
+            let untried = if status == GameStatus::InProgress { game.legal_moves() } else { Vec::new() };
+            nodes.push(Node {
+                input: Some(input),
+                parent: Some(current),
+                children: Vec::new(),
+                untried,
+                player,
+                visits: 0,
+                reward: 0.0,
+            });
+            let child = nodes.len() - 1;
+            nodes[current].children.push(child);
+            current = child;
+        }
+
+        // Simulation: random moves to the end of the game.
+        while status == GameStatus::InProgress {
+            let mut moves = game.legal_moves();
+            let input = moves.swap_remove(self.rng.below(moves.len()));
+            let player = game.current_player();
+            status = game.play(player, &input).expect("Legal move was rejected");
+        }
+
+        // Backpropagation.
+        let mut node = Some(current);
+        while let Some(index) = node {
+            let entry = &mut nodes[index];
+            entry.visits += 1;
+            entry.reward += match status.winner() {
+                Some(winner) if winner == entry.player => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            node = entry.parent;
+        }
+    }
+
+    fn select_child(&self, nodes: &[Node], parent: usize) -> usize {
+        let log_visits = (nodes[parent].visits as f64).ln();
+        let uct = |child: usize| {
+            let node = &nodes[child];
+            node.reward / node.visits as f64
+                + self.exploration * (log_visits / node.visits as f64).sqrt()
+        };
+        *nodes[parent].children.iter()
+            .max_by(|&&a, &&b| uct(a).total_cmp(&uct(b)))
+            .unwrap()
+    }
+}
+
+impl<G: Game> Agent<G> for Mcts {
+    fn choose_move(&mut self, game: &G, _player: Player) -> Vec<usize> {
+        self.best_move(game).expect("No moves left to choose from")
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+#[cfg(test)]
+use crate::board::{Board, Square};
+#[cfg(test)]
+use crate::connectn::ConnectN;
+#[cfg(test)]
+use crate::tictactoe::Tictactoe;
+
+#[cfg(test)]
+fn tictactoe_from(rows: &[&str]) -> Tictactoe {
+    let mut board = Board::new(rows.len());
+    for (row, line) in rows.iter().enumerate() {
+        for (column, mark) in line.chars().enumerate() {
+            match mark {
+                'X' => { board.set_square(column, row, Square::Played(Player::X)); },
+                'O' => { board.set_square(column, row, Square::Played(Player::O)); },
+                _ => (),
+            }
+        }
+    }
+    Tictactoe::new(board)
+}
+
+#[test]
+fn takes_immediate_win() {
+    let game = tictactoe_from(&[
+        "XX ",
+        "OO ",
+        "X  ",
+    ]);
+    let mut mcts = Mcts::new(Budget::Iterations(2000), 1);
+    assert_eq!(mcts.best_move(&game), Some(vec![2, 1]));
+}
+#[test]
+fn blocks_immediate_threat() {
+    let game = tictactoe_from(&[
+        "XX ",
+        "O  ",
+        "   ",
+    ]);
+    let mut mcts = Mcts::new(Budget::Iterations(5000), 1);
+    assert_eq!(mcts.best_move(&game), Some(vec![2, 0]));
+}
+#[test]
+fn same_seed_same_moves() {
+    let game = ConnectN::new(7, 6, 4);
+    let first = Mcts::new(Budget::Iterations(500), 42).best_move(&game);
+    let second = Mcts::new(Budget::Iterations(500), 42).best_move(&game);
+    assert_eq!(first, second);
+}
+#[test]
+fn plays_connect_four_to_the_end() {
+    let mut game = ConnectN::new(7, 6, 4);
+    let mut mcts = Mcts::new(Budget::Iterations(200), 7);
+    let mut status = GameStatus::InProgress;
+    while status == GameStatus::InProgress {
+        let player = game.current_player();
+        let input = mcts.best_move(&game).unwrap();
+        status = game.play(player, &input).unwrap();
+    }
+    assert_eq!(mcts.best_move(&game), None);
+}
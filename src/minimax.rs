@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::agent::{self, Advisor, Agent, Evaluation, SearchInfo};
+use crate::board::{Board, Player, Square};
+use crate::tictactoe::Tictactoe;
+
+// Scores are relative to the side to move. A win found k plies from the
+// current node scores WIN - k, so quicker wins and slower losses are preferred.
+const WIN: i32 = 1_000_000_000;
+const MATE_BOUND: i32 = WIN - 1_000;
+// How often, in nodes, a timed search looks at the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    depth: usize,
+    value: i32,
+    bound: Bound,
+    best: Option<usize>,
+}
+
+// Flat copy of a Tictactoe board, indexed by column * size + row, with every
+// winning line precomputed so a move only has to check the lines through it.
+struct Position {
+    size: usize,
+    cells: Vec<Option<Player>>,
+    lines: Vec<Vec<usize>>,
+    lines_through: Vec<Vec<usize>>,
+    order: Vec<usize>,
+}
+
+fn is_x_or_o(player: Player) -> bool {
+    player == Player::X || player == Player::O
+}
+
+impl Position {
+    fn from_board(board: &Board) -> Position {
+        let size = board.columns();
+        let mut cells = Vec::with_capacity(size * size);
+        for column in 0..size {
+            for row in 0..size {
+                let cell = board.get_square(column, row).and_then(Square::to_option);
+                assert!(cell.is_none_or(is_x_or_o), "Minimax only plays X against O");
+                cells.push(cell);
+            }
+        }
+
+        let index = |column: usize, row: usize| column * size + row;
+        let mut lines: Vec<Vec<usize>> = Vec::with_capacity(2 * size + 2);
+        for i in 0..size {
+            lines.push((0..size).map(|column| index(column, i)).collect());
+            lines.push((0..size).map(|row| index(i, row)).collect());
+        }
+        lines.push((0..size).map(|i| index(i, i)).collect());
+        lines.push((0..size).map(|i| index(size - 1 - i, i)).collect());
+
+        let mut lines_through: Vec<Vec<usize>> = vec![Vec::new(); size * size];
+        for (line_index, line) in lines.iter().enumerate() {
+            for &cell in line {
+                lines_through[cell].push(line_index);
+            }
+        }
+
+        // Cells on more lines (centre, diagonals) are searched first.
+        let mut order: Vec<usize> = (0..size * size).collect();
+        order.sort_by_key(|&cell| std::cmp::Reverse(lines_through[cell].len()));
+
+        Position { size, cells, lines, lines_through, order }
+    }
+
+    // Two bits a cell, which is room for X and O.
+    fn key(&self) -> Option<u128> {
+        if self.cells.len() > 64 {
+            return None
+        }
+        let mut key = 0u128;
+        for cell in &self.cells {
+            key = (key << 2) | match cell {
+                None => 0,
+                Some(player) => player.index() as u128 + 1,
+            };
+        }
+        Some(key)
+    }
+
+    fn is_winning_move(&self, cell: usize, player: Player) -> bool {
+        self.lines_through[cell].iter().any(|&line| {
+            self.lines[line].iter().all(|&other| self.cells[other] == Some(player))
+        })
+    }
+
+    fn winner(&self) -> Option<Player> {
+        self.lines.iter().find_map(|line| {
+            let first = self.cells[line[0]]?;
+            if line.iter().all(|&cell| self.cells[cell] == Some(first)) {
+                Some(first)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn empty_cells(&self) -> usize {
+        self.cells.iter().filter(|cell| cell.is_none()).count()
+    }
+
+    // Static score for depth-limited searches: every line still open to only
+    // one player counts for that player, more so the fuller it is.
+    fn heuristic(&self, player: Player) -> i32 {
+        let mut score: i64 = 0;
+        for line in &self.lines {
+            let mut mine = 0;
+            let mut theirs = 0;
+            for &cell in line {
+                match self.cells[cell] {
+                    Some(owner) if owner == player => mine += 1,
+                    Some(_) => theirs += 1,
+                    None => (),
+                }
+            }
+            if theirs == 0 && mine > 0 {
+                score += 1i64 << (2 * mine).min(40);
+            } else if mine == 0 && theirs > 0 {
+                score -= 1i64 << (2 * theirs).min(40);
+            }
+        }
+        score.clamp(-(MATE_BOUND as i64) + 1, MATE_BOUND as i64 - 1) as i32
+    }
+}
+
+// Moves a score one ply further from the node where it was found.
+fn backup(value: i32) -> i32 {
+    if value > MATE_BOUND {
+        value - 1
+    } else if value < -MATE_BOUND {
+        value + 1
+    } else {
+        value
+    }
+}
+
+// Inverse of backup, used to translate alpha-beta bounds for a child.
+fn unbackup(value: i32) -> i32 {
+    if value > MATE_BOUND {
+        (value + 1).min(WIN)
+    } else if value < -MATE_BOUND {
+        (value - 1).max(-WIN)
+    } else {
+        value
+    }
+}
+
+// Negamax with alpha-beta pruning and a transposition table. With no depth
+// limit it plays perfectly; with one it falls back on a heuristic, which
+// keeps 4x4 and 5x5 boards responsive.
+pub struct Minimax {
+    max_depth: Option<usize>,
+    think_time: Option<Duration>,
+    deadline: Option<Instant>,
+    // Set once the deadline passes; the search in progress is abandoned.
+    out_of_time: bool,
+    table: HashMap<u128, Entry>,
+    nodes: u64,
+    probes: u64,
+    hits: u64,
+    last: Option<SearchInfo>,
+}
+
+impl Minimax {
+    pub fn new() -> Minimax {
+        Minimax::with_limit(None)
+    }
+
+    // Searches `max_depth` plies, at least one, before falling back on the
+    // heuristic.
+    pub fn with_depth(max_depth: usize) -> Minimax {
+        assert!(max_depth > 0, "Minimax has to look at least one move ahead");
+        Minimax::with_limit(Some(max_depth))
+    }
+
+    fn with_limit(max_depth: Option<usize>) -> Minimax {
+        Minimax {
+            max_depth,
+            think_time: None,
+            deadline: None,
+            out_of_time: false,
+            table: HashMap::new(),
+            nodes: 0,
+            probes: 0,
+            hits: 0,
+            last: None,
+        }
+    }
+
+    // Deepens a ply at a time up to the depth limit, if any, and plays from
+    // the deepest search finished within `think_time`. The first ply always
+    // finishes.
+    pub fn with_think_time(mut self, think_time: Duration) -> Minimax {
+        self.think_time = Some(think_time);
+        self
+    }
+
+    // Perfect play where that's cheap, a depth limit where it isn't.
+    pub fn for_board_size(size: usize) -> Minimax {
+        match size {
+            0..=3 => Minimax::new(),
+            4 => Minimax::with_depth(8),
+            5 => Minimax::with_depth(5),
+            _ => Minimax::with_depth(3),
+        }
+    }
+
+    pub fn best_move(&mut self, board: &Board, player: Player) -> Option<(usize, usize)> {
+        self.evaluate(board, player).map(|(square, _)| square)
+    }
+
+    // The best move for player, with its value.
+    pub fn evaluate(&mut self, board: &Board, player: Player) -> Option<((usize, usize), Evaluation)> {
+        assert!(is_x_or_o(player), "Minimax only plays X against O");
+        let mut position = Position::from_board(board);
+        if position.winner().is_some() {
+            return None
+        }
+        let start = Instant::now();
+        self.nodes = 0;
+        self.probes = 0;
+        self.hits = 0;
+        self.deadline = None;
+        self.out_of_time = false;
+        let limit = self.max_depth.unwrap_or(usize::MAX).min(position.empty_cells());
+        let mut depth = if self.think_time.is_some() { 1 } else { limit };
+        let mut best = None;
+        loop {
+            match self.search_root(&mut position, player, depth) {
+                Some(found) if !self.out_of_time => best = Some((found, depth)),
+                _ => break,
+            }
+            if depth >= limit || best.is_some_and(|((_, score), _)| score > MATE_BOUND) {
+                break
+            }
+            depth += 1;
+            self.deadline = self.think_time.map(|think_time| start + think_time);
+        }
+        let ((cell, score), depth) = best?;
+        self.last = Some(SearchInfo {
+            depth,
+            nodes: self.nodes,
+            elapsed: start.elapsed(),
+            principal_variation: self.principal_variation(&mut position, cell, player, depth),
+            table_probes: self.probes,
+            table_hits: self.hits,
+        });
+        let complete = self.max_depth.is_none() && depth == limit;
+        Some(((cell / position.size, cell % position.size), self.evaluation(score, complete)))
+    }
+
+    // The best cell to `depth` plies and its score.
+    fn search_root(&mut self, position: &mut Position, player: Player, depth: usize) -> Option<(usize, i32)> {
+        let mut best: Option<(usize, i32)> = None;
+        let mut alpha = -WIN;
+        for i in 0..position.order.len() {
+            let cell = position.order[i];
+            if position.cells[cell].is_some() {
+                continue
+            }
+            let score = self.score_move(position, cell, player, depth, alpha, WIN);
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((cell, score));
+                alpha = alpha.max(score);
+            }
+        }
+        best
+    }
+
+    // The line from `cell` on, following the table's best moves until the
+    // game ends, the table runs out or the search depth is reached.
+    fn principal_variation(&self, position: &mut Position, cell: usize, player: Player, depth: usize) -> Vec<Vec<usize>> {
+        let mut played = Vec::new();
+        let mut next = Some(cell);
+        let mut player = player;
+        while let Some(cell) = next {
+            position.cells[cell] = Some(player);
+            played.push(cell);
+            if played.len() == depth || position.is_winning_move(cell, player) || position.empty_cells() == 0 {
+                break
+            }
+            player = player.opponent();
+            next = position.key()
+                .and_then(|key| self.table.get(&key))
+                .and_then(|entry| entry.best)
+                .filter(|&best| position.cells[best].is_none());
+        }
+        for &cell in &played {
+            position.cells[cell] = None;
+        }
+        played.into_iter().map(|cell| vec![cell / position.size, cell % position.size]).collect()
+    }
+
+    // `complete` is whether the search saw every line to the end.
+    fn evaluation(&self, score: i32, complete: bool) -> Evaluation {
+        if score > MATE_BOUND {
+            Evaluation::Win((WIN - score) as usize + 1)
+        } else if score < -MATE_BOUND {
+            Evaluation::Loss((WIN + score) as usize + 1)
+        } else if complete {
+            Evaluation::Draw
+        } else {
+            Evaluation::Heuristic(score)
+        }
+    }
+
+    // Plays cell for player, searches the reply and returns the score from
+    // player's point of view.
+    fn score_move(&mut self, position: &mut Position, cell: usize, player: Player, depth: usize, alpha: i32, beta: i32) -> i32 {
+        position.cells[cell] = Some(player);
+        let score = if position.is_winning_move(cell, player) {
+            WIN
+        } else {
+            backup(-self.negamax(position, player.opponent(), depth - 1, unbackup(-beta), unbackup(-alpha)))
+        };
+        position.cells[cell] = None;
+        score
+    }
+
+    fn negamax(&mut self, position: &mut Position, player: Player, depth: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(CLOCK_INTERVAL) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            self.out_of_time = true;
+        }
+        if self.out_of_time {
+            return 0
+        }
+        if position.empty_cells() == 0 {
+            return 0
+        }
+        if depth == 0 {
+            return position.heuristic(player)
+        }
+
+        let original_alpha = alpha;
+        let key = position.key();
+        let mut hint = None;
+        self.probes += key.is_some() as u64;
+        let table = &self.table;
+        if let Some(entry) = key.and_then(|key| table.get(&key)) {
+            self.hits += 1;
+            hint = entry.best;
+            // Bounds only ever cut the search off. Narrowing the window with
+            // them instead would store what is only a bound as exact.
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.value,
+                    Bound::Lower if entry.value >= beta => return entry.value,
+                    Bound::Upper if entry.value <= alpha => return entry.value,
+                    _ => (),
+                }
+            }
+        }
+
+        let mut best_value = -WIN;
+        let mut best_cell = None;
+        let moves = hint.into_iter().chain(position.order.clone());
+        for cell in moves {
+            if position.cells[cell].is_some() || (Some(cell) == hint && best_cell.is_some()) {
+                continue
+            }
+            let value = self.score_move(position, cell, player, depth, alpha, beta);
+            if value > best_value || best_cell.is_none() {
+                best_value = value;
+                best_cell = Some(cell);
+            }
+            alpha = alpha.max(value);
+            if alpha >= beta {
+                break
+            }
+        }
+        // Scores from an abandoned search mustn't go in the table.
+        if self.out_of_time {
+            return 0
+        }
+
+        if let Some(key) = key {
+            let bound = if best_value <= original_alpha {
+                Bound::Upper
+            } else if best_value >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table.insert(key, Entry { depth, value: best_value, bound, best: best_cell });
+        }
+
+        best_value
+    }
+}
+
+impl Default for Minimax {
+    fn default() -> Minimax {
+        Minimax::new()
+    }
+}
+
+impl Agent<Tictactoe> for Minimax {
+    fn choose_move(&mut self, game: &Tictactoe, player: Player) -> Vec<usize> {
+        agent::assert_two_player(game);
+        let (column, row) = self.best_move(game.board(), player)
+            .expect("No moves left to choose from");
+        vec![column, row]
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl Advisor<Tictactoe> for Minimax {
+    fn advise(&mut self, game: &Tictactoe, player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        agent::assert_two_player(game);
+        self.evaluate(game.board(), player)
+            .map(|((column, row), evaluation)| (vec![column, row], evaluation))
+    }
+}
+
+#[cfg(test)]
+use crate::board::Players;
+#[cfg(test)]
+use crate::game::{Game, GameStatus};
+
+#[cfg(test)]
+fn board_from(rows: &[&str]) -> Board {
+    let mut board = Board::new(rows.len());
+    for (row, line) in rows.iter().enumerate() {
+        for (column, mark) in line.chars().enumerate() {
+            match mark {
+                'X' => { board.set_square(column, row, Square::Played(Player::X)); },
+                'O' => { board.set_square(column, row, Square::Played(Player::O)); },
+                _ => (),
+            }
+        }
+    }
+    board
+}
+
+#[cfg(test)]
+fn never_loses(minimax: &mut Minimax, board: &mut Board, to_move: Player, computer: Player) {
+    let position = Position::from_board(board);
+    if let Some(winner) = position.winner() {
+        assert_eq!(winner, computer, "Minimax lost:\n{}", board);
+        return
+    }
+    if position.empty_cells() == 0 {
+        return
+    }
+    if to_move == computer {
+        let (column, row) = minimax.best_move(board, computer).unwrap();
+        board.set_square(column, row, Square::Played(computer));
+        never_loses(minimax, board, to_move.opponent(), computer);
+        board.set_square(column, row, Square::Empty);
+    } else {
+        for column in 0..board.columns() {
+            for row in 0..board.rows() {
+                if board.get_square(column, row) == Some(Square::Empty) {
+                    board.set_square(column, row, Square::Played(to_move));
+                    never_loses(minimax, board, to_move.opponent(), computer);
+                    board.set_square(column, row, Square::Empty);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn takes_immediate_win() {
+    let board = board_from(&[
+        "XX ",
+        "OO ",
+        "   ",
+    ]);
+    assert_eq!(Minimax::new().best_move(&board, Player::X), Some((2, 0)));
+    assert_eq!(Minimax::new().best_move(&board, Player::O), Some((2, 1)));
+}
+#[test]
+fn blocks_immediate_threat() {
+    let board = board_from(&[
+        "XX ",
+        "O  ",
+        "   ",
+    ]);
+    assert_eq!(Minimax::new().best_move(&board, Player::O), Some((2, 0)));
+    let board = board_from(&[
+        "O  ",
+        " X ",
+        "X  ",
+    ]);
+    assert_eq!(Minimax::new().best_move(&board, Player::O), Some((2, 0)));
+}
+#[test]
+fn reports_distance_to_the_end() {
+    let board = board_from(&[
+        "XX ",
+        "OO ",
+        "   ",
+    ]);
+    assert_eq!(Minimax::new().evaluate(&board, Player::X), Some(((2, 0), Evaluation::Win(1))));
+    let board = board_from(&[
+        "X  ",
+        " O ",
+        "  X",
+    ]);
+    assert_eq!(Minimax::new().evaluate(&board, Player::O).map(|(_, evaluation)| evaluation), Some(Evaluation::Draw));
+    let board = board_from(&[
+        "X O",
+        "   ",
+        "   ",
+    ]);
+    // X's next mark sets up a fork O can't stop.
+    assert_eq!(Minimax::new().evaluate(&board, Player::X).map(|(_, evaluation)| evaluation), Some(Evaluation::Win(5)));
+}
+#[test]
+fn never_loses_on_3x3() {
+    let mut minimax = Minimax::new();
+    let mut board = Board::new(3);
+    never_loses(&mut minimax, &mut board, Player::X, Player::X);
+    never_loses(&mut minimax, &mut board, Player::X, Player::O);
+}
+#[test]
+fn finishes_on_larger_boards() {
+    for size in 4..=5 {
+        let mut board = Board::new(size);
+        let mut player = Player::X;
+        let mut minimax = Minimax::for_board_size(size);
+        while let Some((column, row)) = minimax.best_move(&board, player) {
+            board.set_square(column, row, Square::Played(player));
+            player = player.opponent();
+        }
+        assert_eq!(Position::from_board(&board).winner(), None);
+    }
+}
+#[test]
+fn reports_the_expected_line() {
+    let board = board_from(&[
+        "X O",
+        "   ",
+        "   ",
+    ]);
+    let mut minimax = Minimax::new();
+    let (column, row) = minimax.best_move(&board, Player::X).unwrap();
+    let info = minimax.last_search().unwrap();
+    assert_eq!(info.principal_variation[0], vec![column, row]);
+    // The fork takes five plies to win, and the table has all of them.
+    assert_eq!(info.principal_variation.len(), 5);
+    let mut game = Tictactoe::new(board);
+    for input in &info.principal_variation[..4] {
+        let player = game.current_player();
+        assert_eq!(game.play(player, input), Ok(GameStatus::InProgress));
+    }
+    let player = game.current_player();
+    assert_eq!(game.play(player, &info.principal_variation[4]).map(|status| status.winner()), Ok(Some(Player::X)));
+    assert!(info.nodes > 0 && info.table_hits <= info.table_probes);
+}
+#[test]
+fn deepens_within_the_think_time() {
+    let board = board_from(&[
+        "XXXX ",
+        "OOOO ",
+        "     ",
+        "     ",
+        "     ",
+    ]);
+    // With no time at all it still takes a win, which the first ply finds.
+    let mut hurried = Minimax::for_board_size(5).with_think_time(Duration::ZERO);
+    assert_eq!(hurried.best_move(&board, Player::X), Some((4, 0)));
+    assert_eq!(hurried.last_search().unwrap().depth, 1);
+
+    // With plenty, it goes as deep as it would untimed and agrees.
+    let mut timed = Minimax::with_depth(3).with_think_time(Duration::from_secs(60));
+    let mut untimed = Minimax::with_depth(3);
+    let board = Board::new(5);
+    assert_eq!(timed.evaluate(&board, Player::X), untimed.evaluate(&board, Player::X));
+    assert_eq!(timed.last_search().unwrap().depth, 3);
+
+    // On a small board it solves the game, so a draw is a draw.
+    let mut timed = Minimax::new().with_think_time(Duration::from_secs(60));
+    assert_eq!(timed.evaluate(&Board::new(3), Player::X).map(|(_, value)| value), Some(Evaluation::Draw));
+}
+#[test]
+#[should_panic(expected = "only play X against O")]
+fn refuses_a_third_player() {
+    let mut game = Tictactoe::new(Board::new(3)).with_players(Players::new(3));
+    for input in [[0, 0], [1, 1], [2, 2]] {
+        let player = game.current_player();
+        game.play(player, &input).unwrap();
+    }
+    Minimax::new().choose_move(&game, Player::X);
+}
+#[test]
+#[should_panic(expected = "only plays X against O")]
+fn refuses_a_third_players_stones() {
+    // Two bits a cell can't tell a Z from its neighbour's X.
+    let mut board = board_from(&["X  ", " O ", "   "]);
+    board.set_square(2, 2, Square::Played(Player::new(3)));
+    Minimax::new().evaluate(&board, Player::X);
+}
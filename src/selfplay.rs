@@ -0,0 +1,245 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::agent::Agent;
+use crate::board::Player;
+use crate::difficulty::{Difficulty, Leveled};
+use crate::game::{Game, GameStatus};
+use crate::lookahead::{Deepening, Heuristic, Lookahead};
+use crate::mcts::{Budget, Mcts};
+use crate::rng::Rng;
+
+// A computer player as given on the command line: a difficulty level by
+// name, or "mcts:<iterations>", "lookahead:<depth>" or "deepening:<ms>".
+// Timed engines play differently from run to run however they're seeded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EngineSpec {
+    Level(Difficulty),
+    Mcts(usize),
+    Lookahead(usize),
+    Deepening(Duration),
+}
+
+impl FromStr for EngineSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<EngineSpec, String> {
+        if let Some(level) = Difficulty::ALL.iter().find(|level| level.to_string().eq_ignore_ascii_case(text)) {
+            return Ok(EngineSpec::Level(*level))
+        }
+        let (name, number) = text.split_once(':').ok_or_else(|| format!("Unknown engine {:?}", text))?;
+        let number: u64 = number.parse().map_err(|_| format!("Bad number in {:?}", text))?;
+        // None of the engines can do anything with nothing.
+        if number == 0 {
+            return Err(format!("{:?} has to be more than zero", text))
+        }
+        match name {
+            "mcts" => Ok(EngineSpec::Mcts(number as usize)),
+            "lookahead" => Ok(EngineSpec::Lookahead(number as usize)),
+            "deepening" => Ok(EngineSpec::Deepening(Duration::from_millis(number))),
+            _ => Err(format!("Unknown engine {:?}", text)),
+        }
+    }
+}
+
+impl fmt::Display for EngineSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineSpec::Level(level) => write!(f, "{}", level),
+            EngineSpec::Mcts(iterations) => write!(f, "mcts:{}", iterations),
+            EngineSpec::Lookahead(depth) => write!(f, "lookahead:{}", depth),
+            EngineSpec::Deepening(budget) => write!(f, "deepening:{}", budget.as_millis()),
+        }
+    }
+}
+
+impl EngineSpec {
+    // `exact` is the game's strongest engine, as for Leveled, and
+    // `heuristic` scores positions beyond the search horizon, if the game
+    // has a heuristic.
+    pub fn build<G: Game + 'static>(
+        self,
+        exact: impl FnOnce() -> Box<dyn Agent<G>>,
+        heuristic: impl FnOnce() -> Option<Box<dyn Heuristic<G>>>,
+        seed: u64,
+    ) -> Box<dyn Agent<G>> {
+        match (self, heuristic()) {
+            (EngineSpec::Level(level), _) => Box::new(Leveled::new(level, exact(), seed)),
+            (EngineSpec::Mcts(iterations), _) => Box::new(Mcts::new(Budget::Iterations(iterations), seed)),
+            (EngineSpec::Lookahead(depth), Some(heuristic)) => Box::new(Lookahead::with_heuristic(depth, heuristic)),
+            (EngineSpec::Lookahead(depth), None) => Box::new(Lookahead::new(depth)),
+            (EngineSpec::Deepening(budget), Some(heuristic)) => Box::new(Deepening::with_heuristic(budget, heuristic)),
+            (EngineSpec::Deepening(budget), None) => Box::new(Deepening::new(budget)),
+        }
+    }
+}
+
+// Results of a match, counted from the first engine's side.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub games: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    // Results for whichever engine moved first in each game.
+    pub first_player_wins: usize,
+    pub first_player_losses: usize,
+    pub plies: usize,
+}
+
+// Plays `games` games between two engines from the given position, the
+// first engine moving first in even-numbered games and the second in odd
+// ones.
+pub fn play_match<G: Game>(game: &G, first: &mut dyn Agent<G>, second: &mut dyn Agent<G>, games: usize) -> Summary {
+    let mut summary = Summary { games, ..Summary::default() };
+    for round in 0..games {
+        let first_plays = if round % 2 == 0 { Player::X } else { Player::O };
+        let mut position = game.clone();
+        let winner = loop {
+            let player = position.current_player();
+            let input = if player == first_plays {
+                first.choose_move(&position, player)
+            } else {
+                second.choose_move(&position, player)
+            };
+            summary.plies += 1;
+            match position.play(player, &input).expect("Engine made an illegal move") {
+                GameStatus::InProgress => (),
+                GameStatus::Drawn => break None,
+                GameStatus::Won(winner, _) | GameStatus::Connected(winner, _) => break Some(winner),
+            }
+        };
+        match winner {
+            None => summary.draws += 1,
+            Some(winner) => {
+                if winner == first_plays {
+                    summary.wins += 1;
+                } else {
+                    summary.losses += 1;
+                }
+                if winner == Player::X {
+                    summary.first_player_wins += 1;
+                } else {
+                    summary.first_player_losses += 1;
+                }
+            },
+        }
+    }
+    summary
+}
+
+// Seeds for the two engines, drawn from the match seed.
+pub fn engine_seeds(seed: u64) -> (u64, u64) {
+    let mut rng = Rng::new(seed);
+    (rng.next_u64(), rng.next_u64())
+}
+
+// 95% Wilson score interval for a proportion.
+pub fn wilson_interval(successes: usize, trials: usize) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0)
+    }
+    let z = 1.96f64;
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let centre = p + z * z / (2.0 * n);
+    let spread = z * (p * (1.0 - p) / n + z * z / (4.0 * n * n)).sqrt();
+    let denominator = 1.0 + z * z / n;
+    (((centre - spread) / denominator).max(0.0), ((centre + spread) / denominator).min(1.0))
+}
+
+impl Summary {
+    pub fn first_player_draws(&self) -> usize {
+        self.games - self.first_player_wins - self.first_player_losses
+    }
+
+    pub fn average_length(&self) -> f64 {
+        if self.games == 0 { 0.0 } else { self.plies as f64 / self.games as f64 }
+    }
+
+    // The first mover's score, counting draws as half a win, less the half
+    // it would get if moving first made no difference.
+    pub fn first_player_advantage(&self) -> f64 {
+        if self.games == 0 {
+            return 0.0
+        }
+        (self.first_player_wins as f64 + self.first_player_draws() as f64 / 2.0) / self.games as f64 - 0.5
+    }
+}
+
+fn proportion(f: &mut fmt::Formatter<'_>, label: &str, count: usize, games: usize) -> fmt::Result {
+    let (low, high) = wilson_interval(count, games);
+    writeln!(f, "{:<14}{:>6}  {:5.1}%  (95% CI {:.1}% to {:.1}%)", label, count,
+        100.0 * count as f64 / games.max(1) as f64, 100.0 * low, 100.0 * high)
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        proportion(f, "Wins", self.wins, self.games)?;
+        proportion(f, "Draws", self.draws, self.games)?;
+        proportion(f, "Losses", self.losses, self.games)?;
+        writeln!(f, "Average length: {:.1} plies", self.average_length())?;
+        writeln!(f, "First player: {} wins, {} draws, {} losses",
+            self.first_player_wins, self.first_player_draws(), self.first_player_losses)?;
+        let (low, high) = wilson_interval(self.first_player_wins, self.first_player_wins + self.first_player_losses);
+        writeln!(f, "First player advantage: {:+.1}% (wins {:.1}% to {:.1}% of decisive games, 95% CI)",
+            100.0 * self.first_player_advantage(), 100.0 * low, 100.0 * high)
+    }
+}
+
+#[cfg(test)]
+use crate::board::Board;
+#[cfg(test)]
+use crate::minimax::Minimax;
+#[cfg(test)]
+use crate::tictactoe::Tictactoe;
+
+#[cfg(test)]
+fn tictactoe_match(first: EngineSpec, second: EngineSpec, games: usize, seed: u64) -> Summary {
+    let game = Tictactoe::new(Board::new(3));
+    let (first_seed, second_seed) = engine_seeds(seed);
+    let mut first = first.build(|| Box::new(Minimax::new()), || None, first_seed);
+    let mut second = second.build(|| Box::new(Minimax::new()), || None, second_seed);
+    play_match(&game, first.as_mut(), second.as_mut(), games)
+}
+
+#[test]
+fn parses_engine_specs() {
+    assert_eq!("strong".parse(), Ok(EngineSpec::Level(Difficulty::Strong)));
+    assert_eq!("Perfect".parse(), Ok(EngineSpec::Level(Difficulty::Perfect)));
+    assert_eq!("mcts:500".parse(), Ok(EngineSpec::Mcts(500)));
+    assert_eq!("deepening:250".parse(), Ok(EngineSpec::Deepening(Duration::from_millis(250))));
+    assert!("lookahead:deep".parse::<EngineSpec>().is_err());
+    assert!("grandmaster".parse::<EngineSpec>().is_err());
+    for zero in ["mcts:0", "lookahead:0", "deepening:0"] {
+        assert!(zero.parse::<EngineSpec>().is_err(), "{}", zero);
+    }
+    assert_eq!(EngineSpec::Lookahead(3).to_string().parse(), Ok(EngineSpec::Lookahead(3)));
+}
+#[test]
+fn wilson_interval_brackets_the_rate() {
+    let (low, high) = wilson_interval(50, 100);
+    assert!(low < 0.5 && high > 0.5);
+    assert!((0.5 - low - (high - 0.5)).abs() < 1e-9);
+    assert_eq!(wilson_interval(0, 10).0, 0.0);
+    assert!(wilson_interval(0, 10).1 > 0.0);
+    assert!(wilson_interval(10, 10).0 < 1.0);
+}
+#[test]
+fn same_seed_same_results() {
+    let beginner = EngineSpec::Level(Difficulty::Beginner);
+    let mcts = EngineSpec::Mcts(50);
+    let summary = tictactoe_match(beginner, mcts, 20, 7);
+    assert_eq!(summary, tictactoe_match(beginner, mcts, 20, 7));
+    assert_eq!(summary.wins + summary.draws + summary.losses, 20);
+    assert_eq!(summary.first_player_wins + summary.first_player_draws() + summary.first_player_losses, 20);
+}
+#[test]
+fn perfect_play_is_a_draw() {
+    let perfect = EngineSpec::Level(Difficulty::Perfect);
+    let summary = tictactoe_match(perfect, perfect, 4, 1);
+    assert_eq!(summary.draws, 4);
+    assert_eq!(summary.average_length(), 9.0);
+    assert_eq!(summary.first_player_advantage(), 0.0);
+}
@@ -0,0 +1,691 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use crate::agent::{self, Advisor, Agent, Evaluation, SearchInfo};
+use crate::book::Book;
+use crate::board::{Player, Square};
+use crate::connectn::ConnectN;
+use crate::game::{Game, GameStatus};
+
+// Bitboard copy of a ConnectN position. Each column takes rows + 1 bits,
+// bottom to top, with the spare bit on top keeping columns apart so lines
+// can be found with shifts. `current` holds the stones of the side to move
+// and `mask` every stone on the board.
+#[derive(Debug, Copy, Clone)]
+struct Position {
+    current: u128,
+    mask: u128,
+    moves: usize,
+}
+
+#[derive(Debug)]
+struct Geometry {
+    columns: usize,
+    rows: usize,
+    win_length: usize,
+    board_mask: u128,
+    bottom_mask: u128,
+    directions: [isize; 4],
+    order: Vec<usize>,
+}
+
+impl Geometry {
+    fn new(columns: usize, rows: usize, win_length: usize) -> Geometry {
+        let height = rows + 1;
+        assert!(Solver::fits(columns, rows), "Board too large for the solver");
+        let column_bits = (1u128 << rows) - 1;
+        let mut board_mask = 0;
+        let mut bottom_mask = 0;
+        for column in 0..columns {
+            board_mask |= column_bits << (column * height);
+            bottom_mask |= 1u128 << (column * height);
+        }
+
+        // Centre columns first, working outwards.
+        let mut order: Vec<usize> = (0..columns).collect();
+        order.sort_by_key(|&column| (2 * column as isize - columns as isize + 1).abs());
+
+        let height = height as isize;
+        Geometry {
+            columns, rows, win_length,
+            board_mask, bottom_mask,
+            directions: [1, height - 1, height, height + 1],
+            order,
+        }
+    }
+
+    fn cells(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    fn column_mask(&self, column: usize) -> u128 {
+        ((1u128 << self.rows) - 1) << (column * (self.rows + 1))
+    }
+
+    // Empty cells that would complete a line for the owner of `stones`.
+    fn winning_cells(&self, stones: u128, mask: u128) -> u128 {
+        let mut cells = 0;
+        for &direction in &self.directions {
+            for gap in 0..self.win_length {
+                let mut candidates = self.board_mask;
+                for i in 0..self.win_length {
+                    if i != gap {
+                        candidates &= shift(stones, (i as isize - gap as isize) * direction);
+                    }
+                }
+                cells |= candidates;
+            }
+        }
+        cells & (self.board_mask ^ mask)
+    }
+}
+
+// Shifts so that bit `b + offset` of `bits` lands on bit `b`.
+fn shift(bits: u128, offset: isize) -> u128 {
+    if offset >= 0 {
+        bits.checked_shr(offset as u32).unwrap_or(0)
+    } else {
+        bits.checked_shl(-offset as u32).unwrap_or(0)
+    }
+}
+
+impl Position {
+    fn from_game(game: &ConnectN, geometry: &Geometry, player: Player) -> Position {
+        agent::assert_two_player(game);
+        let board = game.board();
+        let mut position = Position { current: 0, mask: 0, moves: 0 };
+        for column in 0..geometry.columns {
+            for row in 0..geometry.rows {
+                if let Some(Square::Played(owner)) = board.get_square(column, row) {
+                    let bit = 1u128 << (column * (geometry.rows + 1) + geometry.rows - 1 - row);
+                    position.mask |= bit;
+                    position.moves += 1;
+                    if owner == player {
+                        position.current |= bit;
+                    }
+                }
+            }
+        }
+        position
+    }
+
+    fn key(&self) -> u128 {
+        self.current + self.mask
+    }
+
+    fn possible(&self, geometry: &Geometry) -> u128 {
+        (self.mask + geometry.bottom_mask) & geometry.board_mask
+    }
+
+    fn play(&mut self, bit: u128) {
+        self.current ^= self.mask;
+        self.mask |= bit;
+        self.moves += 1;
+    }
+
+    fn can_win_next(&self, geometry: &Geometry) -> bool {
+        geometry.winning_cells(self.current, self.mask) & self.possible(geometry) != 0
+    }
+
+    // Playable cells that don't hand the opponent an immediate win.
+    fn non_losing_moves(&self, geometry: &Geometry) -> u128 {
+        let mut possible = self.possible(geometry);
+        let opponent_wins = geometry.winning_cells(self.current ^ self.mask, self.mask);
+        let forced = possible & opponent_wins;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                return 0
+            }
+            possible = forced;
+        }
+        possible & !(opponent_wins >> 1)
+    }
+
+    fn move_score(&self, geometry: &Geometry, bit: u128) -> u32 {
+        geometry.winning_cells(self.current | bit, self.mask | bit).count_ones()
+    }
+}
+
+// Transposition table shared between search threads. Each slot holds the
+// two halves of a position's key, both xored with the stored value, and the
+// value itself, so a slot torn by two threads writing at once fails the key
+// check instead of giving a wrong value.
+struct Table {
+    slots: Vec<[AtomicU64; 3]>,
+}
+
+const TABLE_BITS: u32 = 20;
+
+impl Table {
+    fn new() -> Table {
+        Table { slots: (0..1usize << TABLE_BITS).map(|_| Default::default()).collect() }
+    }
+
+    fn slot(key: u128) -> usize {
+        let mixed = (key as u64 ^ (key >> 64) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (mixed >> (64 - TABLE_BITS)) as usize
+    }
+
+    // Values must be non-zero; zero marks an empty slot.
+    fn lookup(&self, key: u128) -> Option<i32> {
+        let [low, high, value] = &self.slots[Table::slot(key)];
+        let value = value.load(Ordering::Relaxed);
+        if value != 0
+            && low.load(Ordering::Relaxed) ^ value == key as u64
+            && high.load(Ordering::Relaxed) ^ value == (key >> 64) as u64 {
+            Some(value as u32 as i32)
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, key: u128, value: i32) {
+        let [low, high, stored] = &self.slots[Table::slot(key)];
+        let value = value as u32 as u64;
+        low.store(key as u64 ^ value, Ordering::Relaxed);
+        high.store((key >> 64) as u64 ^ value, Ordering::Relaxed);
+        stored.store(value, Ordering::Relaxed);
+    }
+}
+
+// Search statistics, added up over all threads.
+#[derive(Default)]
+struct Counters {
+    nodes: AtomicU64,
+    probes: AtomicU64,
+    hits: AtomicU64,
+}
+
+// How many moves of the expected line to work out after a move. Each one
+// takes a search, if mostly of positions already in the table.
+const VARIATION_LENGTH: usize = 8;
+
+// Negamax with alpha-beta pruning, centre-first move ordering and a
+// transposition table of upper bounds. Scores follow the usual convention
+// for connect four solvers: positive if the side to move wins, larger the
+// sooner it wins; zero for a draw.
+//
+// With more than one thread each position is solved lazy SMP style: every
+// thread runs the whole search over the shared table, helpers trying moves
+// near the root in a different order so they fill the table ahead of the
+// others, and the first to finish gives the answer.
+pub struct Solver {
+    geometry: Geometry,
+    table: Table,
+    book: Option<Book>,
+    threads: usize,
+    // Whether to work out the expected line after each move, or only give
+    // the move itself.
+    variation: bool,
+    counters: Counters,
+    last: Option<SearchInfo>,
+}
+
+// One thread's search.
+struct Worker<'a> {
+    geometry: &'a Geometry,
+    table: &'a Table,
+    // Set once some thread has the answer, telling the rest to give up.
+    stop: &'a AtomicBool,
+    // Zero for the main thread; helpers reorder moves near the root by it.
+    variation: usize,
+    root_moves: usize,
+    counters: &'a Counters,
+    // This thread's share of the counters, added to them once it's done.
+    nodes: Cell<u64>,
+    probes: Cell<u64>,
+    hits: Cell<u64>,
+}
+
+impl Solver {
+    pub fn new(columns: usize, rows: usize, win_length: usize) -> Solver {
+        Solver {
+            geometry: Geometry::new(columns, rows, win_length),
+            table: Table::new(),
+            book: None,
+            threads: 1,
+            variation: false,
+            counters: Counters::default(),
+            last: None,
+        }
+    }
+
+    // Positions in the book are answered from it without searching.
+    pub fn with_book(mut self, book: Book) -> Solver {
+        self.book = Some(book);
+        self
+    }
+
+    // Searches on `threads` threads, sharing one transposition table.
+    pub fn with_threads(mut self, threads: usize) -> Solver {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // Works out the expected line after each move, for showing. That takes
+    // more searches, so it's off unless asked for.
+    pub fn with_variation(mut self, variation: bool) -> Solver {
+        self.variation = variation;
+        self
+    }
+
+    // Whether a board of this size fits the solver's bitboards.
+    pub fn fits(columns: usize, rows: usize) -> bool {
+        columns * (rows + 1) <= 128
+    }
+
+    pub fn for_game(game: &ConnectN) -> Solver {
+        Solver::new(game.board().columns(), game.board().rows(), game.win_length())
+    }
+
+    fn solve_position(&self, position: &Position) -> i32 {
+        let stop = AtomicBool::new(false);
+        let worker = |variation| Worker {
+            geometry: &self.geometry,
+            table: &self.table,
+            stop: &stop,
+            variation,
+            root_moves: position.moves,
+            counters: &self.counters,
+            nodes: Cell::new(0),
+            probes: Cell::new(0),
+            hits: Cell::new(0),
+        };
+        if self.threads == 1 {
+            return worker(0).solve(position).unwrap()
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for variation in 0..self.threads {
+                let sender = sender.clone();
+                let worker = worker(variation);
+                scope.spawn(move || {
+                    if let Some(score) = worker.solve(position) {
+                        worker.stop.store(true, Ordering::Relaxed);
+                        // Only the first answer is read.
+                        let _ = sender.send(score);
+                    }
+                });
+            }
+            receiver.recv().unwrap()
+        })
+    }
+    fn evaluation(&self, score: i32, moves: usize) -> Evaluation {
+        let remaining = (self.geometry.cells() + 1 - moves) as i32;
+        if score > 0 {
+            Evaluation::Win(2 * (remaining / 2 - score) as usize + 1)
+        } else if score < 0 {
+            Evaluation::Loss(2 * ((remaining - 1) / 2 + score) as usize + 2)
+        } else {
+            Evaluation::Draw
+        }
+    }
+
+    // Exact value of the position with `player` to move. The game must still
+    // be in progress.
+    pub fn solve(&mut self, game: &ConnectN, player: Player) -> Evaluation {
+        let position = Position::from_game(game, &self.geometry, player);
+        let score = self.solve_position(&position);
+        self.evaluation(score, position.moves)
+    }
+
+    // Every playable column with its exact value for `player`.
+    pub fn evaluate_moves(&mut self, game: &ConnectN, player: Player) -> Vec<(usize, Evaluation)> {
+        let position = Position::from_game(game, &self.geometry, player);
+        let possible = position.possible(&self.geometry);
+        let winning = self.geometry.winning_cells(position.current, position.mask);
+        let mut results = Vec::new();
+        for column in self.geometry.order.clone() {
+            let bit = possible & self.geometry.column_mask(column);
+            if bit == 0 {
+                continue
+            }
+            let evaluation = if bit & winning != 0 {
+                Evaluation::Win(1)
+            } else {
+                let mut child = position;
+                child.play(bit);
+                if child.moves == self.geometry.cells() {
+                    Evaluation::Draw
+                } else {
+                    let score = -self.solve_position(&child);
+                    self.evaluation(score, position.moves)
+                }
+            };
+            results.push((column, evaluation));
+        }
+        results
+    }
+
+    pub fn best_move(&mut self, game: &ConnectN, player: Player) -> Option<(usize, Evaluation)> {
+        agent::assert_two_player(game);
+        self.last = None;
+        if let Some(entry) = self.book.as_ref().and_then(|book| book.lookup(game.board())) {
+            return Some(entry)
+        }
+        let start = Instant::now();
+        for counter in [&self.counters.nodes, &self.counters.probes, &self.counters.hits] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        let mut best: Option<(usize, Evaluation)> = None;
+        for (column, evaluation) in self.evaluate_moves(game, player) {
+            if best.is_none_or(|(_, best_evaluation)| evaluation > best_evaluation) {
+                best = Some((column, evaluation));
+            }
+        }
+
+        let (column, _) = best?;
+        let position = Position::from_game(game, &self.geometry, player);
+        // Counted before working out the line, which searches again.
+        let info = SearchInfo {
+            depth: self.geometry.cells() - position.moves,
+            nodes: self.counters.nodes.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+            principal_variation: Vec::new(),
+            table_probes: self.counters.probes.load(Ordering::Relaxed),
+            table_hits: self.counters.hits.load(Ordering::Relaxed),
+        };
+        let principal_variation = if self.variation { self.principal_variation(position, column) } else { vec![vec![column]] };
+        self.last = Some(SearchInfo { principal_variation, ..info });
+        best
+    }
+
+    // The line starting with `column`: each move after it is the first, in
+    // search order, that keeps the position's value.
+    fn principal_variation(&self, mut position: Position, column: usize) -> Vec<Vec<usize>> {
+        let mut line = Vec::new();
+        let mut next = Some(column);
+        while let Some(column) = next {
+            line.push(vec![column]);
+            let bit = position.possible(&self.geometry) & self.geometry.column_mask(column);
+            let won = self.geometry.winning_cells(position.current, position.mask) & bit != 0;
+            position.play(bit);
+            if won || position.moves == self.geometry.cells() || line.len() == VARIATION_LENGTH {
+                break
+            }
+            let score = self.solve_position(&position);
+            let possible = position.possible(&self.geometry);
+            let winning = self.geometry.winning_cells(position.current, position.mask);
+            next = self.geometry.order.iter().copied().find(|&column| {
+                let bit = possible & self.geometry.column_mask(column);
+                if bit == 0 {
+                    return false
+                }
+                if bit & winning != 0 {
+                    return true
+                }
+                let mut child = position;
+                child.play(bit);
+                let value = if child.moves == self.geometry.cells() { 0 } else { -self.solve_position(&child) };
+                value == score
+            });
+        }
+        line
+    }
+}
+
+impl Worker<'_> {
+    // None if another thread finished first.
+    fn negamax(&self, position: &Position, mut alpha: i32, mut beta: i32) -> Option<i32> {
+        if self.stop.load(Ordering::Relaxed) {
+            return None
+        }
+        self.nodes.set(self.nodes.get() + 1);
+        let cells = self.geometry.cells() as i32;
+        let moves = position.moves as i32;
+
+        let next = position.non_losing_moves(self.geometry);
+        if next == 0 {
+            return Some(-(cells - moves) / 2)
+        }
+        if moves >= cells - 2 {
+            return Some(0)
+        }
+
+        let min = -(cells - 2 - moves) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return Some(alpha)
+            }
+        }
+
+        // Table values are stored offset by the number of cells so they're
+        // never zero.
+        let mut max = (cells - 1 - moves) / 2;
+        self.probes.set(self.probes.get() + 1);
+        if let Some(value) = self.table.lookup(position.key()) {
+            self.hits.set(self.hits.get() + 1);
+            max = value - cells;
+        }
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return Some(beta)
+            }
+        }
+
+        let mut candidates: Vec<(u128, u32)> = self.geometry.order.iter()
+            .map(|&column| next & self.geometry.column_mask(column))
+            .filter(|&bit| bit != 0)
+            .map(|bit| (bit, position.move_score(self.geometry, bit)))
+            .collect();
+        // Stable, so equally promising moves keep their centre-first order.
+        candidates.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        if self.variation != 0 && position.moves < self.root_moves + 2 {
+            let len = candidates.len();
+            candidates.rotate_left(self.variation % len);
+        }
+
+        for (bit, _) in candidates {
+            let mut child = *position;
+            child.play(bit);
+            let score = -self.negamax(&child, -beta, -alpha)?;
+            if score >= beta {
+                return Some(score)
+            }
+            if score > alpha {
+                alpha = score
+            }
+        }
+
+        self.table.store(position.key(), alpha + cells);
+        Some(alpha)
+    }
+
+    fn solve(&self, position: &Position) -> Option<i32> {
+        let score = self.search(position);
+        for (counter, count) in [
+            (&self.counters.nodes, &self.nodes),
+            (&self.counters.probes, &self.probes),
+            (&self.counters.hits, &self.hits),
+        ] {
+            counter.fetch_add(count.take(), Ordering::Relaxed);
+        }
+        score
+    }
+
+    fn search(&self, position: &Position) -> Option<i32> {
+        let cells = self.geometry.cells() as i32;
+        let moves = position.moves as i32;
+        if position.can_win_next(self.geometry) {
+            return Some((cells + 1 - moves) / 2)
+        }
+
+        // Narrow the window with null-window searches until it closes.
+        let mut min = -(cells - moves) / 2;
+        let mut max = (cells + 1 - moves) / 2;
+        while min < max {
+            let mut middle = min + (max - min) / 2;
+            if middle <= 0 && min / 2 < middle {
+                middle = min / 2;
+            } else if middle >= 0 && max / 2 > middle {
+                middle = max / 2;
+            }
+            let result = self.negamax(position, middle, middle + 1)?;
+            if result <= middle {
+                max = result;
+            } else {
+                min = result;
+            }
+        }
+        Some(min)
+    }
+}
+
+impl Agent<ConnectN> for Solver {
+    fn choose_move(&mut self, game: &ConnectN, player: Player) -> Vec<usize> {
+        let (column, _) = self.best_move(game, player)
+            .expect("No moves left to choose from");
+        vec![column]
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl Advisor<ConnectN> for Solver {
+    fn advise(&mut self, game: &ConnectN, player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return None
+        }
+        self.best_move(game, player).map(|(column, evaluation)| (vec![column], evaluation))
+    }
+
+    fn analyze(&mut self, game: &ConnectN, player: Player) -> Vec<(Vec<usize>, Evaluation)> {
+        if game.get_status() != GameStatus::InProgress {
+            return Vec::new()
+        }
+        self.evaluate_moves(game, player).into_iter()
+            .map(|(column, evaluation)| (vec![column], evaluation))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+fn game_from_moves(columns: usize, rows: usize, win_length: usize, moves: &str) -> (ConnectN, Player) {
+    let mut game = ConnectN::new(columns, rows, win_length);
+    let mut player = Player::X;
+    for column in moves.chars() {
+        let column = column.to_digit(10).unwrap() as usize - 1;
+        assert_eq!(game.play(player, &[column]), Ok(GameStatus::InProgress));
+        player = player.opponent();
+    }
+    (game, player)
+}
+
+// Plain minimax through the Game trait, to check the solver against.
+#[cfg(test)]
+fn brute_force(game: &ConnectN, player: Player, seen: &mut std::collections::HashMap<String, Evaluation>) -> Evaluation {
+    let key = game.to_string();
+    if let Some(&evaluation) = seen.get(&key) {
+        return evaluation
+    }
+    let mut best = None;
+    for column in 0..game.board().columns() {
+        let mut child = game.clone();
+        let evaluation = match child.play(player, &[column]) {
+            Err(_) => continue,
+            Ok(GameStatus::Won(..) | GameStatus::Connected(..)) => Evaluation::Win(1),
+            Ok(GameStatus::Drawn) => Evaluation::Draw,
+            Ok(GameStatus::InProgress) => match brute_force(&child, player.opponent(), seen) {
+                Evaluation::Win(distance) => Evaluation::Loss(distance + 1),
+                Evaluation::Loss(distance) => Evaluation::Win(distance + 1),
+                other => other,
+            },
+        };
+        if best.is_none_or(|best| evaluation > best) {
+            best = Some(evaluation);
+        }
+    }
+    seen.insert(key, best.unwrap());
+    best.unwrap()
+}
+
+#[test]
+fn takes_immediate_win_and_blocks() {
+    let (game, player) = game_from_moves(5, 4, 4, "112233");
+    let mut solver = Solver::for_game(&game);
+    assert_eq!(solver.best_move(&game, player), Some((3, Evaluation::Win(1))));
+
+    let (game, player) = game_from_moves(5, 4, 4, "1122335");
+    assert_eq!(solver.best_move(&game, player).map(|(column, _)| column), Some(3));
+}
+#[test]
+fn matches_brute_force_on_small_boards() {
+    for &(columns, rows, win_length, moves) in &[
+        (3, 3, 3, ""),
+        (4, 3, 3, ""),
+        (4, 4, 3, "1"),
+        (4, 4, 3, "23"),
+        (5, 4, 4, "3344"),
+        (4, 4, 4, "12"),
+    ] {
+        let (game, player) = game_from_moves(columns, rows, win_length, moves);
+        let mut solver = Solver::for_game(&game);
+        assert_eq!(solver.solve(&game, player), brute_force(&game, player, &mut std::collections::HashMap::new()),
+            "{}x{} connect {} after {:?}", columns, rows, win_length, moves);
+    }
+}
+#[test]
+fn solves_mid_game_connect_four() {
+    for &(moves, expected) in &[
+        ("2252576253462244111563365343671351441", Evaluation::Loss(4)),
+        ("52461125423375", Evaluation::Loss(26)),
+        ("2256177761131247", Evaluation::Win(21)),
+    ] {
+        let (game, player) = game_from_moves(7, 6, 4, moves);
+        let mut solver = Solver::for_game(&game);
+        assert_eq!(solver.solve(&game, player), expected, "after {}", moves);
+
+        // The best move is worth exactly as much as the position.
+        let (_, best) = solver.best_move(&game, player).unwrap();
+        assert_eq!(best, expected, "after {}", moves);
+    }
+}
+#[test]
+fn threads_agree_with_a_single_thread() {
+    for &(columns, rows, win_length, moves) in &[
+        (4, 4, 3, "23"),
+        (5, 4, 4, "3344"),
+        (7, 6, 4, "2252576253462244111563365343671351441"),
+        (7, 6, 4, "2256177761131247"),
+    ] {
+        let (game, player) = game_from_moves(columns, rows, win_length, moves);
+        let mut single = Solver::for_game(&game);
+        let mut parallel = Solver::for_game(&game).with_threads(4);
+        assert_eq!(parallel.evaluate_moves(&game, player), single.evaluate_moves(&game, player),
+            "{}x{} connect {} after {:?}", columns, rows, win_length, moves);
+    }
+}
+#[test]
+fn reports_the_expected_line() {
+    let (game, player) = game_from_moves(7, 6, 4, "2252576253462244111563365343671351441");
+    let mut solver = Solver::for_game(&game).with_variation(true);
+    let (column, evaluation) = solver.best_move(&game, player).unwrap();
+    assert_eq!(evaluation, Evaluation::Loss(4));
+    let info = solver.last_search().unwrap();
+    assert_eq!(info.principal_variation[0], vec![column]);
+    assert!(info.nodes > 0 && info.table_hits <= info.table_probes);
+    // The line holds out for as long as the loss says, and then loses.
+    assert_eq!(info.principal_variation.len(), 4);
+    let mut position = game.clone();
+    let mut to_move = player;
+    let mut status = GameStatus::InProgress;
+    for input in &info.principal_variation {
+        status = position.play(to_move, input).unwrap();
+        to_move = to_move.opponent();
+    }
+    assert_eq!(status.winner(), Some(player.opponent()));
+
+    // Unasked for, the line is just the move.
+    let mut solver = Solver::for_game(&game);
+    solver.best_move(&game, player);
+    assert_eq!(solver.last_search().unwrap().principal_variation, vec![vec![column]]);
+}
@@ -0,0 +1,539 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::agent::{self, Advisor, Agent, Evaluation, SearchInfo};
+use crate::board::{Board, Player, Square, Symmetry};
+use crate::game::{Game, GameStatus};
+use crate::tictactoe::Tictactoe;
+
+// Tablebase files are plain text, like opening books: a header line
+//
+//     tictactoe-tablebase <version> <board size>
+//
+// then one line per position stored: its code, its value, its best cell and
+// the size of its orbit, as in Entry.
+pub const VERSION: u32 = 2;
+const MAGIC: &str = "tictactoe-tablebase";
+
+// Positions are packed into base 3 codes, one digit per cell indexed by
+// column * size + row: 0 for empty, 1 for X and 2 for O. The first cell is
+// the most significant digit, so codes order the way Board::canonical
+// orders boards, and the smallest code of a position's symmetric copies is
+// its canonical board's.
+const X: u32 = 1;
+const O: u32 = 2;
+const NO_MOVE: u8 = u8::MAX;
+// Value of a game the side to move has already lost. Zero plies can't be
+// told apart from a draw by sign alone.
+const LOST: i8 = i8::MIN;
+
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    // Plies to the end for the side to move: positive if it wins, negative
+    // if it loses, zero for a draw.
+    value: i8,
+    // Best cell in the canonical position's coordinates, or NO_MOVE once
+    // the game is over.
+    best: u8,
+    // How many distinct positions this one stands for under symmetry.
+    orbit: u8,
+}
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(io::Error),
+    Format(String),
+    UnsupportedVersion(u32),
+    WrongSize { expected: usize, found: usize },
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TablebaseError::Io(error) => write!(f, "{}", error),
+            TablebaseError::Format(message) => write!(f, "Malformed tablebase: {}", message),
+            TablebaseError::UnsupportedVersion(version) => write!(f, "Unsupported tablebase version {}", version),
+            TablebaseError::WrongSize { expected, found } => write!(f,
+                "Tablebase is for {}x{} boards, not {}x{}", found, found, expected, expected),
+        }
+    }
+}
+
+impl From<io::Error> for TablebaseError {
+    fn from(error: io::Error) -> TablebaseError {
+        TablebaseError::Io(error)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub positions: usize,
+    pub x_wins: usize,
+    pub o_wins: usize,
+    pub draws: usize,
+}
+
+// Totals with and without symmetry reduction, over every reachable
+// position and over finished games only. Wins and draws are the results of
+// perfect play from each position.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub all: Totals,
+    pub canonical: Totals,
+    pub terminal: Totals,
+    pub canonical_terminal: Totals,
+}
+
+// Every reachable Tictactoe position on a small square board, with its value
+// and best move, found by retrograde analysis: positions are generated layer
+// by layer from the empty board, then valued from the last layer back, each
+// from its already valued successors. Positions related by one of the eight
+// rotations and reflections of the square are stored once, under their
+// canonical board. Copies share their entries.
+#[derive(Clone)]
+pub struct Tablebase {
+    size: usize,
+    powers: Vec<u32>,
+    // transforms[t][cell] is where the t-th symmetry moves cell.
+    transforms: Vec<Vec<usize>>,
+    lines: Vec<Vec<usize>>,
+    entries: Arc<HashMap<u32, Entry>>,
+    last: Option<SearchInfo>,
+}
+
+impl Tablebase {
+    pub fn generate(size: usize) -> Tablebase {
+        let mut tablebase = Tablebase::empty(size);
+        let layers = tablebase.generate_layers();
+        let mut entries = HashMap::new();
+        for layer in layers.iter().rev() {
+            for &code in layer {
+                let entry = tablebase.solve(&entries, code);
+                entries.insert(code, entry);
+            }
+        }
+        tablebase.entries = Arc::new(entries);
+        tablebase
+    }
+
+    fn empty(size: usize) -> Tablebase {
+        assert!(size * size <= 20, "Tablebases only cover small boards");
+        let cells = size * size;
+        let powers = (0..cells as u32).map(|i| 3u32.pow(cells as u32 - 1 - i)).collect();
+
+        let index = |column: usize, row: usize| column * size + row;
+        let transforms = Symmetry::ALL.iter().map(|symmetry| {
+            let mut transform = vec![0; cells];
+            for column in 0..size {
+                for row in 0..size {
+                    let (to_column, to_row) = symmetry.apply((column, row), size, size);
+                    transform[index(column, row)] = index(to_column, to_row);
+                }
+            }
+            transform
+        }).collect();
+
+        let mut lines: Vec<Vec<usize>> = Vec::new();
+        for i in 0..size {
+            lines.push((0..size).map(|column| index(column, i)).collect());
+            lines.push((0..size).map(|row| index(i, row)).collect());
+        }
+        lines.push((0..size).map(|i| index(i, i)).collect());
+        lines.push((0..size).map(|i| index(size - 1 - i, i)).collect());
+
+        Tablebase { size, powers, transforms, lines, entries: Arc::new(HashMap::new()), last: None }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{} {} {}", MAGIC, VERSION, self.size)?;
+        let mut codes: Vec<&u32> = self.entries.keys().collect();
+        codes.sort();
+        for code in codes {
+            let entry = self.entries[code];
+            writeln!(writer, "{} {} {} {}", code, entry.value, entry.best, entry.orbit)?;
+        }
+        Ok(())
+    }
+
+    // Reads a tablebase, failing unless it was generated for boards of
+    // `size`.
+    pub fn read<R: BufRead>(reader: R, size: usize) -> Result<Tablebase, TablebaseError> {
+        let mut lines = reader.lines();
+        let header = lines.next().ok_or_else(|| TablebaseError::Format("empty file".to_string()))??;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        let numbers: Option<Vec<usize>> = fields.iter().skip(1).map(|field| field.parse().ok()).collect();
+        let numbers = match numbers {
+            Some(numbers) if fields.len() == 3 && fields[0] == MAGIC => numbers,
+            _ => return Err(TablebaseError::Format(format!("bad header {:?}", header))),
+        };
+        if numbers[0] != VERSION as usize {
+            return Err(TablebaseError::UnsupportedVersion(numbers[0] as u32))
+        }
+        if numbers[1] != size {
+            return Err(TablebaseError::WrongSize { expected: size, found: numbers[1] })
+        }
+
+        let mut tablebase = Tablebase::empty(size);
+        let mut entries = HashMap::new();
+        for line in lines {
+            let line = line?;
+            let (code, entry) = Entry::parse(&line)
+                .filter(|&(code, entry)| tablebase.fits(code, entry))
+                .ok_or_else(|| TablebaseError::Format(format!("bad entry {:?}", line)))?;
+            entries.insert(code, entry);
+        }
+        tablebase.entries = Arc::new(entries);
+        Ok(tablebase)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: &Path, size: usize) -> Result<Tablebase, TablebaseError> {
+        Tablebase::read(BufReader::new(File::open(path)?), size)
+    }
+
+    // Whether an entry read from a file could be one of ours: a code for a
+    // board of our size, an orbit the square's symmetries allow, and a best
+    // move, if there is one, to an empty cell. Anything else would have
+    // lookup suggest moves that can't be played.
+    fn fits(&self, code: u32, entry: Entry) -> bool {
+        let cells = self.size * self.size;
+        if code as u64 >= 3u64.pow(cells as u32) || ![1, 2, 4, 8].contains(&entry.orbit) {
+            return false
+        }
+        entry.best == NO_MOVE || ((entry.best as usize) < cells && self.decode(code)[entry.best as usize] == 0)
+    }
+
+    fn decode(&self, code: u32) -> Vec<u32> {
+        self.powers.iter().map(|&power| code / power % 3).collect()
+    }
+
+    fn encode(&self, board: &Board) -> u32 {
+        let mut code = 0;
+        for column in 0..self.size {
+            for row in 0..self.size {
+                code = code * 3 + match board.get_square(column, row) {
+                    Some(Square::Played(Player::X)) => X,
+                    Some(Square::Played(Player::O)) => O,
+                    _ => 0,
+                };
+            }
+        }
+        code
+    }
+
+    // The code of each of the eight transformed copies of a position. This
+    // is Board::canonical's work done on codes, which generating the 4x4
+    // tablebase needs to be quick.
+    fn transformed_codes(&self, cells: &[u32]) -> Vec<u32> {
+        self.transforms.iter().map(|transform| {
+            cells.iter().enumerate()
+                .map(|(cell, &digit)| digit * self.powers[transform[cell]])
+                .sum()
+        }).collect()
+    }
+
+    // Canonical code of the position after `stone` is put on `cell`.
+    fn child(&self, codes: &[u32], cell: usize, stone: u32) -> u32 {
+        codes.iter().zip(&self.transforms)
+            .map(|(&code, transform)| code + stone * self.powers[transform[cell]])
+            .min()
+            .unwrap()
+    }
+
+    fn winner(&self, cells: &[u32]) -> Option<u32> {
+        self.lines.iter().find_map(|line| {
+            let first = cells[line[0]];
+            if first != 0 && line.iter().all(|&cell| cells[cell] == first) {
+                Some(first)
+            } else {
+                None
+            }
+        })
+    }
+
+    fn to_move(cells: &[u32]) -> u32 {
+        let stones = cells.iter().filter(|&&digit| digit != 0).count();
+        if stones % 2 == 0 { X } else { O }
+    }
+
+    fn generate_layers(&self) -> Vec<Vec<u32>> {
+        let mut layers = vec![vec![0]];
+        loop {
+            let mut next = HashSet::new();
+            for &code in layers.last().unwrap() {
+                let cells = self.decode(code);
+                if self.winner(&cells).is_some() {
+                    continue
+                }
+                let codes = self.transformed_codes(&cells);
+                let stone = Tablebase::to_move(&cells);
+                for (cell, &digit) in cells.iter().enumerate() {
+                    if digit == 0 {
+                        next.insert(self.child(&codes, cell, stone));
+                    }
+                }
+            }
+            if next.is_empty() {
+                return layers
+            }
+            let mut layer: Vec<u32> = next.into_iter().collect();
+            layer.sort_unstable();
+            layers.push(layer);
+        }
+    }
+
+    // Values `code` from its successors' entries.
+    fn solve(&self, entries: &HashMap<u32, Entry>, code: u32) -> Entry {
+        let cells = self.decode(code);
+        let codes = self.transformed_codes(&cells);
+        let orbit = codes.iter().collect::<HashSet<_>>().len() as u8;
+
+        if self.winner(&cells).is_some() {
+            return Entry { value: LOST, best: NO_MOVE, orbit }
+        }
+
+        let stone = Tablebase::to_move(&cells);
+        let mut best: Option<(Evaluation, usize)> = None;
+        for (cell, &digit) in cells.iter().enumerate() {
+            if digit != 0 {
+                continue
+            }
+            let child = entries[&self.child(&codes, cell, stone)];
+            let value = child.evaluation().backed_up();
+            if best.is_none_or(|(best_value, _)| value > best_value) {
+                best = Some((value, cell));
+            }
+        }
+
+        match best {
+            // A full board with no winner.
+            None => Entry { value: 0, best: NO_MOVE, orbit },
+            Some((value, cell)) => Entry { value: Entry::pack(value), best: cell as u8, orbit },
+        }
+    }
+
+    // The best move and its value, or None if the game is over or the
+    // position can't arise in play.
+    pub fn lookup(&self, board: &Board) -> Option<((usize, usize), Evaluation)> {
+        if board.columns() != self.size || board.rows() != self.size {
+            return None
+        }
+        let (canonical, symmetry) = board.canonical(false);
+        let entry = self.entries.get(&self.encode(&canonical))?;
+        if entry.best == NO_MOVE {
+            return None
+        }
+        // Map the canonical best move back onto this board.
+        let best = (entry.best as usize / self.size, entry.best as usize % self.size);
+        Some((symmetry.inverse().apply(best, self.size, self.size), entry.evaluation()))
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut summary = Summary::default();
+        for (&code, entry) in self.entries.iter() {
+            let cells = self.decode(code);
+            let x_to_move = Tablebase::to_move(&cells) == X;
+            let terminal = entry.best == NO_MOVE;
+            let orbit = entry.orbit as usize;
+            let count = |totals: &mut Totals, weight: usize| {
+                totals.positions += weight;
+                match (entry.evaluation(), x_to_move) {
+                    (Evaluation::Win(_), true) | (Evaluation::Loss(_), false) => totals.x_wins += weight,
+                    (Evaluation::Win(_), false) | (Evaluation::Loss(_), true) => totals.o_wins += weight,
+                    _ => totals.draws += weight,
+                }
+            };
+            count(&mut summary.all, orbit);
+            count(&mut summary.canonical, 1);
+            if terminal {
+                count(&mut summary.terminal, orbit);
+                count(&mut summary.canonical_terminal, 1);
+            }
+        }
+        summary
+    }
+}
+
+impl Entry {
+    // A line of a tablebase file: the code and the entry for it.
+    fn parse(line: &str) -> Option<(u32, Entry)> {
+        let mut fields = line.split(' ');
+        let code = fields.next()?.parse().ok()?;
+        let value = fields.next()?.parse().ok()?;
+        let best = fields.next()?.parse().ok()?;
+        let orbit = fields.next()?.parse().ok()?;
+        if fields.next().is_some() {
+            return None
+        }
+        Some((code, Entry { value, best, orbit }))
+    }
+
+    fn pack(evaluation: Evaluation) -> i8 {
+        match evaluation {
+            Evaluation::Win(distance) => distance as i8,
+            Evaluation::Loss(distance) => -(distance as i8),
+            _ => 0,
+        }
+    }
+
+    fn evaluation(&self) -> Evaluation {
+        match self.value {
+            LOST => Evaluation::Loss(0),
+            0 => Evaluation::Draw,
+            value if value > 0 => Evaluation::Win(value as usize),
+            value => Evaluation::Loss(-value as usize),
+        }
+    }
+}
+
+impl fmt::Display for Totals {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} positions: {} X wins, {} O wins, {} draws",
+            self.positions, self.x_wins, self.o_wins, self.draws)
+    }
+}
+
+impl Agent<Tictactoe> for Tablebase {
+    // Nothing is searched; the expected line is looked up move by move to
+    // the end of the game.
+    fn choose_move(&mut self, game: &Tictactoe, player: Player) -> Vec<usize> {
+        agent::assert_two_player(game);
+        let start = Instant::now();
+        let mut line = Vec::new();
+        let mut position = game.clone();
+        let mut to_move = player;
+        while let Some(((column, row), _)) = self.lookup(position.board()) {
+            line.push(vec![column, row]);
+            if position.play(to_move, &[column, row]) != Ok(GameStatus::InProgress) {
+                break
+            }
+            to_move = to_move.opponent();
+        }
+        self.last = Some(SearchInfo {
+            depth: line.len(),
+            nodes: 0,
+            elapsed: start.elapsed(),
+            principal_variation: line.clone(),
+            table_probes: line.len() as u64,
+            table_hits: line.len() as u64,
+        });
+        line.into_iter().next().expect("Position not in the tablebase")
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl Advisor<Tictactoe> for Tablebase {
+    fn advise(&mut self, game: &Tictactoe, _player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        agent::assert_two_player(game);
+        self.lookup(game.board()).map(|((column, row), evaluation)| (vec![column, row], evaluation))
+    }
+}
+
+#[cfg(test)]
+use crate::minimax::Minimax;
+#[cfg(test)]
+use crate::rng::Rng;
+
+#[test]
+fn matches_known_3x3_figures() {
+    let summary = Tablebase::generate(3).summary();
+    assert_eq!(summary.all.positions, 5478);
+    assert_eq!(summary.canonical.positions, 765);
+    assert_eq!(summary.terminal, Totals { positions: 958, x_wins: 626, o_wins: 316, draws: 16 });
+    assert_eq!(summary.canonical_terminal, Totals { positions: 138, x_wins: 91, o_wins: 44, draws: 3 });
+    // The empty board is a draw.
+    assert_eq!(Tablebase::generate(3).lookup(&Board::new(3)).map(|(_, value)| value), Some(Evaluation::Draw));
+}
+#[test]
+fn agrees_with_minimax() {
+    let tablebase = Tablebase::generate(3);
+    let mut minimax = Minimax::new();
+    let mut board = Board::new(3);
+    // Walk a few games, comparing values and checking the looked up move
+    // really is on an empty square of this board, not the canonical one.
+    for first in 0..9 {
+        board.reset();
+        board.set_square(first / 3, first % 3, Square::Played(Player::X));
+        let mut player = Player::O;
+        while let Some(((column, row), value)) = tablebase.lookup(&board) {
+            let (_, expected) = minimax.evaluate(&board, player).unwrap();
+            assert_eq!(value, expected, "\n{}", board);
+            assert_eq!(board.get_square(column, row), Some(Square::Empty));
+            board.set_square(column, row, Square::Played(player));
+            player = player.opponent();
+        }
+    }
+}
+#[test]
+fn analysis_labels_every_move() {
+    let mut tablebase = Tablebase::generate(3);
+    let mut game = Tictactoe::new(Board::new(3));
+    for input in [[0, 0], [1, 0], [0, 1], [1, 1]] {
+        let player = game.current_player();
+        game.play(player, &input).unwrap();
+    }
+    let analysis = tablebase.analyze(&game, Player::X);
+    assert_eq!(analysis.len(), 5);
+    assert!(analysis.contains(&(vec![0, 2], Evaluation::Win(1))));
+    // Leaving O's column open loses on the next move.
+    assert!(analysis.contains(&(vec![2, 2], Evaluation::Loss(2))));
+    let (best, value) = tablebase.lookup(game.board()).unwrap();
+    assert_eq!(value, Evaluation::Win(1));
+    assert_eq!(best, (0, 2));
+}
+#[test]
+fn codes_agree_with_board_canonical() {
+    let tablebase = Tablebase::generate(3);
+    let mut rng = Rng::new(11);
+    for _ in 0..50 {
+        let mut game = Tictactoe::new(Board::new(3));
+        while game.get_status() == GameStatus::InProgress {
+            let cells = tablebase.decode(tablebase.encode(game.board()));
+            let smallest = tablebase.transformed_codes(&cells).into_iter().min().unwrap();
+            let (canonical, _) = game.board().canonical(false);
+            assert_eq!(smallest, tablebase.encode(&canonical), "\n{}", game.board());
+            let moves = game.legal_moves();
+            let player = game.current_player();
+            game.play(player, &moves[rng.below(moves.len())]).unwrap();
+        }
+    }
+}
+#[test]
+fn round_trips_through_text() {
+    let tablebase = Tablebase::generate(3);
+    let mut text = Vec::new();
+    tablebase.write(&mut text).unwrap();
+    let read = Tablebase::read(&text[..], 3).unwrap();
+    assert_eq!(read.summary(), tablebase.summary());
+    let mut board = Board::new(3);
+    board.set_square(1, 1, Square::Played(Player::X));
+    assert_eq!(read.lookup(&board), tablebase.lookup(&board));
+
+    assert!(matches!(Tablebase::read(&text[..], 4), Err(TablebaseError::WrongSize { expected: 4, found: 3 })));
+    assert!(matches!(Tablebase::read(&b"tictactoe-tablebase 9 3\n"[..], 3), Err(TablebaseError::UnsupportedVersion(9))));
+    assert!(matches!(Tablebase::read(&b"tictactoe-tablebase 2 3\n0 0 4\n"[..], 3), Err(TablebaseError::Format(_))));
+}
+#[test]
+fn rejects_entries_that_dont_fit_the_board() {
+    let read = |entry: &str| Tablebase::read(format!("tictactoe-tablebase 2 3\n{}\n", entry).as_bytes(), 3);
+    assert!(read("0 0 4 1").is_ok());
+    assert!(read("0 0 255 1").is_ok());
+    // Moves off the board and onto the X in the centre, a code too big for
+    // 3x3, and orbits no position has.
+    for entry in ["0 0 9 1", "0 0 200 1", "81 0 4 1", "19683 0 4 1", "0 0 4 0", "0 0 4 3"] {
+        assert!(matches!(read(entry), Err(TablebaseError::Format(_))), "{}", entry);
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::agent::{self, Advisor, Agent, Evaluation, SearchInfo};
+use crate::board::{Board, Player, Square};
+use crate::connectn::ConnectN;
+use crate::game::{Game, GameStatus};
+
+// Threat-space search for ConnectN boards too big to search in full, such as
+// 15x15 with five in a row. Only the attacker's threatening moves are tried:
+//
+// - a four fills all but one cell of a window. If that cell is playable the
+//   defender has to take it, so the reply is forced;
+// - a three fills all but two cells, and is only worth playing when ignoring
+//   it would let the attacker make two playable fours at once. Every reply
+//   is then tried, so wins found through threes are still forced.
+//
+// A line found is a real forced win. Failing to find one only means there is
+// none made of threats within `max_depth` attacking moves, or none found by
+// the deadline.
+pub struct ThreatSearch {
+    max_depth: usize,
+    nodes: u64,
+    deadline: Option<Instant>,
+}
+
+// Every window of win_length cells on the board, found with the row, column
+// and diagonal iterators. On a board that wraps, each window is counted
+// once, however many times its line's iterator goes past it.
+fn windows(board: &Board, win_length: usize) -> Vec<Vec<(usize, usize)>> {
+    let mut lines = Vec::new();
+    for row in 0..board.rows() {
+        lines.push(board.row_iterator(row));
+    }
+    for column in 0..board.columns() {
+        lines.push(board.column_iterator(column));
+    }
+    if let Some(diagonals) = board.diagonal_iterator_iterator(win_length) {
+        lines.extend(diagonals);
+    }
+
+    let mut windows = Vec::new();
+    for mut line in lines {
+        let mut cells = Vec::new();
+        while let Some((position, _)) = line.next_with_position() {
+            cells.push(position);
+        }
+        let count = match line.loop_length() {
+            Some(squares) if squares < win_length => 0,
+            Some(squares) => squares,
+            None => cells.len(),
+        };
+        windows.extend(cells.windows(win_length).take(count).map(<[_]>::to_vec));
+    }
+    windows
+}
+
+// The squares of the board that a game's search works on, with the windows
+// worked out once up front.
+struct Position {
+    board: Board,
+    windows: Vec<Vec<(usize, usize)>>,
+}
+
+impl Position {
+    fn new(game: &ConnectN) -> Position {
+        let board = game.board().clone();
+        let windows = windows(&board, game.win_length());
+        Position { board, windows }
+    }
+
+    // The row a stone dropped in `column` lands on.
+    fn landing_row(&self, column: usize) -> Option<usize> {
+        (0..self.board.rows()).rev()
+            .find(|&row| self.board.get_square(column, row) == Some(Square::Empty))
+    }
+
+    fn playable(&self, (column, row): (usize, usize)) -> bool {
+        self.landing_row(column) == Some(row)
+    }
+
+    fn columns(&self) -> Vec<usize> {
+        (0..self.board.columns()).filter(|&column| self.landing_row(column).is_some()).collect()
+    }
+
+    fn drop(&mut self, column: usize, player: Player) {
+        let row = self.landing_row(column).expect("Column is full");
+        self.board.set_square(column, row, Square::Played(player));
+    }
+
+    fn undo(&mut self, column: usize) {
+        let row = self.landing_row(column).map_or(0, |row| row + 1);
+        self.board.set_square(column, row, Square::Empty);
+    }
+
+    // Empty cells of windows `player` could still complete, with how many of
+    // the window's cells are still empty.
+    fn open_windows(&self, player: Player) -> impl Iterator<Item = (usize, Vec<(usize, usize)>)> + '_ {
+        self.windows.iter().filter_map(move |window| {
+            let mut empty = Vec::new();
+            for &(column, row) in window {
+                match self.board.get_square(column, row) {
+                    Some(Square::Empty) => empty.push((column, row)),
+                    Some(Square::Played(owner)) if owner == player => (),
+                    _ => return None,
+                }
+            }
+            Some((empty.len(), empty))
+        })
+    }
+
+    // Columns `player` could win in right away.
+    fn winning_columns(&self, player: Player) -> Vec<usize> {
+        let mut columns: Vec<usize> = self.open_windows(player)
+            .filter(|(empty, cells)| *empty == 1 && self.playable(cells[0]))
+            .map(|(_, cells)| cells[0].0)
+            .collect();
+        columns.sort_unstable();
+        columns.dedup();
+        columns
+    }
+
+    // Columns where `player` would make a four, then those where it would
+    // make a three.
+    fn threat_columns(&self, player: Player) -> Vec<usize> {
+        let mut fours = HashSet::new();
+        let mut threes = HashSet::new();
+        for (empty, cells) in self.open_windows(player) {
+            for &cell in &cells {
+                if self.playable(cell) {
+                    match empty {
+                        2 => fours.insert(cell.0),
+                        3 => threes.insert(cell.0),
+                        _ => false,
+                    };
+                }
+            }
+        }
+        let mut columns: Vec<usize> = fours.iter().copied().collect();
+        columns.sort_unstable();
+        let mut rest: Vec<usize> = threes.difference(&fours).copied().collect();
+        rest.sort_unstable();
+        columns.extend(rest);
+        columns
+    }
+
+    // Whether `player`, moving now, could make two fours at once.
+    fn has_double_four(&mut self, player: Player) -> bool {
+        for column in self.threat_columns(player) {
+            self.drop(column, player);
+            let double = self.winning_columns(player).len() >= 2;
+            self.undo(column);
+            if double {
+                return true
+            }
+        }
+        false
+    }
+}
+
+impl ThreatSearch {
+    pub fn new(max_depth: usize) -> ThreatSearch {
+        ThreatSearch { max_depth, nodes: 0, deadline: None }
+    }
+
+    // Positions looked at by the last search.
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // A forced win for `attacker`, moving next: the columns of the winning
+    // line, alternating with the defender's replies and ending with the
+    // winning move. Where the defender has a choice the longest defence is
+    // followed, though the attacker may have a quicker win than the one
+    // found. None if no win within the search depth.
+    pub fn find_win(&mut self, game: &ConnectN, attacker: Player) -> Option<Vec<usize>> {
+        agent::assert_two_player(game);
+        self.nodes = 0;
+        if game.get_status() != GameStatus::InProgress {
+            return None
+        }
+        self.attack(&mut Position::new(game), attacker, self.max_depth)
+    }
+
+    fn attack(&mut self, position: &mut Position, attacker: Player, depth: usize) -> Option<Vec<usize>> {
+        self.nodes += 1;
+        if let Some(&column) = position.winning_columns(attacker).first() {
+            return Some(vec![column])
+        }
+        // Out of time, only wins on the spot are still found.
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return None
+        }
+        // The defender's own threats would have to be answered first, which
+        // is beyond a search that only follows the attacker's.
+        if depth == 0 || !position.winning_columns(attacker.opponent()).is_empty() {
+            return None
+        }
+        for column in position.threat_columns(attacker) {
+            position.drop(column, attacker);
+            let line = self.defend(position, attacker, depth);
+            position.undo(column);
+            if let Some(mut line) = line {
+                line.insert(0, column);
+                return Some(line)
+            }
+        }
+        None
+    }
+
+    fn defend(&mut self, position: &mut Position, attacker: Player, depth: usize) -> Option<Vec<usize>> {
+        self.nodes += 1;
+        let defender = attacker.opponent();
+        if !position.winning_columns(defender).is_empty() {
+            return None
+        }
+        let threats = position.winning_columns(attacker);
+        let replies = match threats.len() {
+            // Two fours: block one and lose to the other.
+            2.. => return Some(vec![threats[0], threats[1]]),
+            1 => threats,
+            // A three is only a threat if it's answered.
+            _ if depth > 1 && position.has_double_four(attacker) => position.columns(),
+            _ => return None,
+        };
+        // Every reply has to lose for the defender, who picks the one that
+        // holds out longest.
+        let mut main_line: Option<Vec<usize>> = None;
+        for column in replies {
+            position.drop(column, defender);
+            let line = self.attack(position, attacker, depth - 1);
+            position.undo(column);
+            let mut line = line?;
+            line.insert(0, column);
+            if main_line.as_ref().is_none_or(|main_line| line.len() > main_line.len()) {
+                main_line = Some(line);
+            }
+        }
+        main_line
+    }
+}
+
+// Plays forced wins when the search finds one and stops the opponent's,
+// and leaves every other move to `fallback`. The threat search runs after
+// the fallback's and keeps to its own think time, if it has one, so the two
+// should share the time for a move between them.
+pub struct ThreatGuided<A> {
+    search: ThreatSearch,
+    fallback: A,
+    think_time: Option<Duration>,
+    // Threat search nodes since the last move was chosen.
+    nodes: u64,
+    last: Option<SearchInfo>,
+}
+
+impl<A> ThreatGuided<A> {
+    pub fn new(max_depth: usize, fallback: A) -> ThreatGuided<A> {
+        ThreatGuided { search: ThreatSearch::new(max_depth), fallback, think_time: None, nodes: 0, last: None }
+    }
+
+    pub fn with_think_time(mut self, think_time: Duration) -> ThreatGuided<A> {
+        self.think_time = Some(think_time);
+        self
+    }
+
+    fn start_clock(&mut self) {
+        self.search.deadline = self.think_time.map(|think_time| Instant::now() + think_time);
+    }
+
+    fn find_win(&mut self, game: &ConnectN, attacker: Player) -> Option<Vec<usize>> {
+        let line = self.search.find_win(game, attacker);
+        self.nodes += self.search.nodes();
+        line
+    }
+
+    // A column answering the position's threats for `player`: one that wins
+    // by force, or else one that leaves the opponent without a forced win.
+    // None if nothing is threatened, or nothing helps. A win comes with the
+    // line that forces it.
+    fn threat_move(&mut self, game: &ConnectN, player: Player, preferred: Option<usize>) -> Option<(Vec<usize>, Evaluation)> {
+        // The line found may not be the quickest, so this is how long the
+        // win takes at most.
+        if let Some(line) = self.find_win(game, player) {
+            let distance = line.len();
+            return Some((line, Evaluation::Win(distance)))
+        }
+        self.find_win(game, player.opponent())?;
+
+        let mut columns: Vec<usize> = game.legal_moves().into_iter().map(|input| input[0]).collect();
+        // Try the fallback's choice first, then the centre outwards.
+        let centre = game.board().columns() as isize - 1;
+        columns.sort_by_key(|&column| (Some(column) != preferred, (2 * column as isize - centre).abs()));
+        columns.into_iter().find(|&column| {
+            let mut child = game.clone();
+            child.play(player, &[column]) == Ok(GameStatus::InProgress)
+                && self.find_win(&child, player.opponent()).is_none()
+        }).map(|column| (vec![column], Evaluation::Heuristic(0)))
+    }
+}
+
+impl<A: Agent<ConnectN>> Agent<ConnectN> for ThreatGuided<A> {
+    fn choose_move(&mut self, game: &ConnectN, player: Player) -> Vec<usize> {
+        let preferred = self.fallback.choose_move(game, player);
+        let start = Instant::now();
+        self.nodes = 0;
+        self.start_clock();
+        match self.threat_move(game, player, Some(preferred[0])) {
+            Some((line, evaluation)) if line[0] != preferred[0] || matches!(evaluation, Evaluation::Win(_)) => {
+                self.last = Some(SearchInfo {
+                    depth: line.len(),
+                    nodes: self.nodes,
+                    elapsed: start.elapsed(),
+                    principal_variation: line.iter().map(|&column| vec![column]).collect(),
+                    ..SearchInfo::default()
+                });
+                vec![line[0]]
+            },
+            _ => {
+                self.last = self.fallback.last_search();
+                preferred
+            },
+        }
+    }
+
+    fn last_search(&self) -> Option<SearchInfo> {
+        self.last.clone()
+    }
+}
+
+impl<A: Advisor<ConnectN>> Advisor<ConnectN> for ThreatGuided<A> {
+    fn advise(&mut self, game: &ConnectN, player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        let advice = self.fallback.advise(game, player)?;
+        self.start_clock();
+        // The fallback's evaluation is of its own move, so a defence it
+        // didn't choose goes with the threat search's.
+        match self.threat_move(game, player, Some(advice.0[0])) {
+            Some((line, evaluation)) if line[0] != advice.0[0] || matches!(evaluation, Evaluation::Win(_)) =>
+                Some((vec![line[0]], evaluation)),
+            _ => Some(advice),
+        }
+    }
+
+    fn analyze(&mut self, game: &ConnectN, player: Player) -> Vec<(Vec<usize>, Evaluation)> {
+        let mut analysis = self.fallback.analyze(game, player);
+        self.start_clock();
+        if let Some(line) = self.search.find_win(game, player) {
+            for (input, evaluation) in &mut analysis {
+                if input[0] == line[0] && !matches!(evaluation, Evaluation::Win(_)) {
+                    *evaluation = Evaluation::Win(line.len());
+                }
+            }
+        }
+        analysis
+    }
+}
+
+#[cfg(test)]
+use crate::lookahead::Lookahead;
+#[cfg(test)]
+use crate::solver::Solver;
+
+// Advises the same column whatever the position, sure that it wins.
+#[cfg(test)]
+struct Stubborn(usize);
+
+#[cfg(test)]
+impl Advisor<ConnectN> for Stubborn {
+    fn advise(&mut self, _game: &ConnectN, _player: Player) -> Option<(Vec<usize>, Evaluation)> {
+        Some((vec![self.0], Evaluation::Win(1)))
+    }
+}
+
+#[cfg(test)]
+fn game_from_moves(columns: usize, rows: usize, win_length: usize, moves: &[usize]) -> ConnectN {
+    let mut game = ConnectN::new(columns, rows, win_length);
+    for &column in moves {
+        let player = game.current_player();
+        assert_eq!(game.play(player, &[column]), Ok(GameStatus::InProgress));
+    }
+    game
+}
+
+#[test]
+fn finds_an_open_four() {
+    // X has three on the bottom row with both ends open.
+    let game = game_from_moves(15, 15, 5, &[6, 6, 7, 7, 8, 8]);
+    let mut search = ThreatSearch::new(2);
+    let line = search.find_win(&game, Player::X).unwrap();
+    assert!(line[0] == 5 || line[0] == 9, "{:?}", line);
+    assert_eq!(line.len(), 3);
+}
+#[test]
+fn wins_through_a_chain_of_threats() {
+    let game = game_from_moves(7, 6, 4, &[5, 2, 4, 1, 4, 1, 5, 6, 0, 4, 3, 6, 4, 0]);
+    assert_eq!(ThreatSearch::new(3).find_win(&game, Player::X), None);
+    let line = ThreatSearch::new(4).find_win(&game, Player::X).unwrap();
+    assert_eq!(line.len(), 9);
+    // The line alternates, ending on X's winning move, and really is a win.
+    let mut child = game.clone();
+    let mut player = Player::X;
+    for (ply, &column) in line.iter().enumerate() {
+        let status = child.play(player, &[column]).unwrap();
+        assert_eq!(status.winner() == Some(Player::X), ply + 1 == line.len());
+        player = player.opponent();
+    }
+    assert!(matches!(Solver::for_game(&game).solve(&game, Player::X), Evaluation::Win(distance) if distance <= 9));
+}
+#[test]
+fn no_forced_win_from_the_start() {
+    let game = game_from_moves(15, 15, 5, &[7, 7]);
+    let mut search = ThreatSearch::new(3);
+    assert_eq!(search.find_win(&game, Player::X), None);
+    assert!(search.nodes() > 0);
+}
+#[test]
+fn defends_against_an_open_three() {
+    // X to move next threatens an open four; O has to stop it now.
+    let game = game_from_moves(15, 15, 5, &[6, 14, 7, 14, 8]);
+    let mut guided = ThreatGuided::new(2, Lookahead::new(1));
+    let column = guided.choose_move(&game, Player::O)[0];
+    let mut child = game.clone();
+    child.play(Player::O, &[column]).unwrap();
+    assert_eq!(ThreatSearch::new(2).find_win(&child, Player::X), None, "O played {}", column);
+}
+#[test]
+fn threat_search_keeps_to_its_think_time() {
+    // X's forced win takes a deep search, which there's no time for.
+    let game = game_from_moves(7, 6, 4, &[5, 2, 4, 1, 4, 1, 5, 6, 0, 4, 3, 6, 4, 0]);
+    let mut search = ThreatSearch::new(4);
+    search.deadline = Some(Instant::now());
+    assert_eq!(search.find_win(&game, Player::X), None);
+    assert_eq!(search.nodes(), 1);
+    // Out of time, it's the fallback's move.
+    let mut hurried = ThreatGuided::new(4, Lookahead::new(1)).with_think_time(Duration::ZERO);
+    hurried.choose_move(&game, Player::X);
+    assert_eq!(hurried.last_search().unwrap().depth, 1);
+    // With time, the win is found.
+    let mut timed = ThreatGuided::new(4, Lookahead::new(1)).with_think_time(Duration::from_secs(60));
+    let line = ThreatSearch::new(4).find_win(&game, Player::X).unwrap();
+    assert_eq!(timed.choose_move(&game, Player::X), vec![line[0]]);
+    assert_eq!(timed.last_search().unwrap().depth, line.len());
+}
+#[test]
+fn advice_to_defend_says_what_the_defence_is_worth() {
+    let game = game_from_moves(15, 15, 5, &[6, 14, 7, 14, 8]);
+    let mut guided = ThreatGuided::new(2, Stubborn(0));
+    let (input, evaluation) = guided.advise(&game, Player::O).unwrap();
+    assert_ne!(input, vec![0]);
+    assert_eq!(evaluation, Evaluation::Heuristic(0));
+    // Where nothing is threatened, the fallback's advice stands.
+    let game = game_from_moves(15, 15, 5, &[7, 7]);
+    assert_eq!(guided.advise(&game, Player::X), Some((vec![0], Evaluation::Win(1))));
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::board::{Board, Player, Square};
+use crate::board::{column_name, Board, Line, Player, Players, Square, Wrap, DIRECTIONS};
 
 use crate::game::{
     Game,
@@ -8,14 +8,63 @@ use crate::game::{
     GameError,
 };
 
+// Played on an n by n board to fill a whole line, or as an m,n,k game: on
+// any rectangle, first to get k in a row.
+#[derive(Clone)]
 pub struct Tictactoe {
-    board: Board
+    board: Board,
+    win_length: usize,
+    players: Players,
+    // Kept up to date by `play`, which only has to look at the lines
+    // through the square just played.
+    status: GameStatus,
+    empty_squares: usize,
 }
 
 impl Tictactoe {
 
     pub fn new(board: Board) -> Tictactoe {
-        Tictactoe { board }
+        let win_length = board.columns();
+        Tictactoe::with_win_length(board, win_length)
+    }
+
+    // The m,n,k game on a `columns` by `rows` board.
+    pub fn mnk(columns: usize, rows: usize, win_length: usize) -> Tictactoe {
+        Tictactoe::with_win_length(Board::new_rectangle(columns, rows), win_length)
+    }
+
+    fn with_win_length(board: Board, win_length: usize) -> Tictactoe {
+        let mut game = Tictactoe { board, win_length, players: Players::default(), status: GameStatus::InProgress, empty_squares: 0 };
+        game.empty_squares = game.legal_moves().len();
+        game.status = game.scan_status();
+        game
+    }
+
+    pub fn with_players(mut self, players: Players) -> Tictactoe {
+        self.players = players;
+        self
+    }
+
+    // Lines may run off one side of the board and on at the other, or also
+    // off the top and on at the bottom.
+    pub fn with_wrap(mut self, wrap: Wrap) -> Tictactoe {
+        self.board = self.board.with_wrap(wrap);
+        self.status = self.scan_status();
+        self
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn win_length(&self) -> usize {
+        self.win_length
+    }
+
+    // Whether a win takes a whole row, column or diagonal of a flat square
+    // board.
+    fn is_whole_line(&self) -> bool {
+        self.board.columns() == self.board.rows() && self.win_length == self.board.columns() && self.board.wrap() == Wrap::Flat
     }
 
     fn is_winning_row(&self, row: usize) -> Option<Player> {
@@ -50,36 +99,83 @@ impl Tictactoe {
         self.is_winning(&mut self.board.diagonal_iterator(self.board.columns() -1, false))
     }
 
-    fn detect_win(&self) -> Option<Player> {
+    fn detect_win(&self) -> Option<(Player, Line)> {
+        if !self.is_whole_line() {
+            return self.board.find_line(self.win_length)
+                .and_then(|line| self.board.get_square(line.start.0, line.start.1)?.to_option().map(|player| (player, line)))
+        }
         let len = self.board.columns();
+        let line = |start, step| Line::flat(start, step, len);
         for i in 0..len {
             if let Some(winner) = self.is_winning_row(i) {
-                println!("Winning row {}", i);
-                return Some(winner)
+                return Some((winner, line((0, i), (1, 0))))
             }
 
             if let Some(winner) = self.is_winning_column(i) {
-                println!("Winning column {}", i);
-                return Some(winner)
+                return Some((winner, line((i, 0), (0, 1))))
             }
         }
 
         if let Some(winner) = self.is_winning_forward_diagonal() {
-            println!("Winning forward diagonal");
-            return Some(winner)
+            return Some((winner, line((0, 0), (1, 1))))
         }
 
         if let Some(winner) = self.is_winning_reverse_diagonal() {
-            println!("Winning reverse diagonal");
-            return Some(winner)
+            return Some((winner, line((len - 1, 0), (-1, 1))))
         }
 
         None
     }
 
+    // Where detect_win comes to a line: rows and columns in turn, then the
+    // diagonals. Other boards are scanned one direction at a time, in the
+    // order of DIRECTIONS.
+    fn scan_order(&self, line: &Line) -> usize {
+        let len = self.board.columns();
+        if !self.is_whole_line() {
+            return DIRECTIONS.iter().position(|&(column, row)| (column, row, 0) == line.step).unwrap()
+        }
+        match line.step {
+            (1, 0, 0) => 2 * line.start.1,
+            (0, 1, 0) => 2 * line.start.0 + 1,
+            (1, 1, 0) => 2 * len,
+            _ => 2 * len + 1,
+        }
+    }
+
+    fn scan_status(&self) -> GameStatus {
+        if let Some((player, line)) = self.detect_win() {
+            GameStatus::Won(player, line)
+        } else if self.detect_draw() {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    // The status once `player` has marked (column, row). A new line has to
+    // run through that square, unless someone had already won; then the
+    // full scan decides who, as it always has.
+    fn status_after(&self, column: usize, row: usize, player: Player) -> GameStatus {
+        if self.status != GameStatus::InProgress {
+            return self.scan_status()
+        }
+        let won = DIRECTIONS.iter()
+            .map(|&step| self.board.run_through(column, row, step))
+            .filter(|line| line.length >= self.win_length)
+            .min_by_key(|line| self.scan_order(line));
+        if let Some(line) = won {
+            GameStatus::Won(player, line)
+        } else if self.empty_squares == 0 {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
     fn detect_draw(&self) -> bool {
         for column in 0..self.board.columns() {
-            for row in 0..self.board.columns() {
+            for row in 0..self.board.rows() {
                 if Some(Square::Empty) == self.board.get_square(column, row) {
                     return false
                 }
@@ -92,23 +188,19 @@ impl Tictactoe {
 impl Game for Tictactoe {
 
     fn get_status(&self) -> GameStatus {
-        if let Some(player) = self.detect_win() {
-            GameStatus::Won(player)
-        } else if self.detect_draw() {
-            GameStatus::Drawn
-        } else {
-            GameStatus::InProgress
-        }
+        self.status.clone()
     }
 
-    fn play(&mut self, player: Player, input: &Vec<usize>) -> Result<GameStatus, GameError> {
+    fn play(&mut self, player: Player, input: &[usize]) -> Result<GameStatus, GameError> {
         let column = input[0];
         let row = input[1];
         match self.board.get_square(column, row) {
             Some(Square::Played(_)) => Err(GameError::SquareNotEmpty),
             Some(Square::Empty) => {
                 self.board.set_square(column, row, Square::Played(player));
-                Ok(self.get_status())
+                self.empty_squares -= 1;
+                self.status = self.status_after(column, row, player);
+                Ok(self.status.clone())
             },
             None => Err(GameError::OutOfBounds),
         }
@@ -117,15 +209,182 @@ impl Game for Tictactoe {
 
     fn reset(&mut self) {
         self.board.reset();
+        self.empty_squares = self.board.columns() * self.board.rows();
+        self.status = GameStatus::InProgress;
     }
 
     fn num_inputs(&self) -> usize {
-        return 2;
+        2
+    }
+
+    fn annotate(&self, labels: Vec<(Vec<usize>, String)>) -> String {
+        let cells = labels.into_iter().map(|(input, label)| ((input[0], input[1]), label)).collect();
+        self.board.annotated(cells, Vec::new()).with_marks(self.players.square_marks()).to_string()
+    }
+
+    fn legal_moves(&self) -> Vec<Vec<usize>> {
+        let mut moves = Vec::new();
+        for column in 0..self.board.columns() {
+            for row in 0..self.board.rows() {
+                if Some(Square::Empty) == self.board.get_square(column, row) {
+                    moves.push(vec![column, row]);
+                }
+            }
+        }
+        moves
+    }
+
+    fn current_player(&self) -> Player {
+        self.players.to_move(self.board.columns() * self.board.rows() - self.empty_squares)
+    }
+
+    fn players(&self) -> &Players {
+        &self.players
+    }
+
+    fn position_hash(&self) -> u64 {
+        self.board.hash() ^ self.board.zobrist().side_to_move(self.current_player())
+    }
+
+    // Like chess: the column's letter, then the row's number, counting from
+    // one at the top as the board is printed.
+    fn notation(&self, input: &[usize]) -> String {
+        format!("{}{}", column_name(input[0]), input[1] + 1)
     }
 }
 
 impl fmt::Display for Tictactoe {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.board)
+        write!(f, "{}", self.board.annotated(Vec::new(), Vec::new()).with_marks(self.players.square_marks()))
+    }
+}
+
+#[cfg(test)]
+use crate::rng::Rng;
+
+#[test]
+fn incremental_status_matches_the_full_scan() {
+    let mut rng = Rng::new(1);
+    for size in 1..=7 {
+        for _ in 0..200 {
+            let mut game = Tictactoe::new(Board::new(size));
+            assert_eq!(game.get_status(), game.scan_status());
+            // Going on past the end of the game, until the board fills up.
+            while !game.legal_moves().is_empty() {
+                let moves = game.legal_moves();
+                let player = game.current_player();
+                let status = game.play(player, &moves[rng.below(moves.len())]).unwrap();
+                assert_eq!(status, game.scan_status(), "\n{}", game);
+            }
+        }
     }
 }
+#[test]
+fn wins_say_where_the_line_is() {
+    let won = |moves: &[(usize, usize)]| {
+        let mut game = Tictactoe::new(Board::new(3));
+        let mut status = GameStatus::InProgress;
+        for &(column, row) in moves {
+            let player = game.current_player();
+            status = game.play(player, &[column, row]).unwrap();
+        }
+        status
+    };
+    let line = |start, step| Line::flat(start, step, 3);
+    assert_eq!(won(&[(0, 1), (0, 0), (1, 1), (1, 0), (2, 1)]), GameStatus::Won(Player::X, line((0, 1), (1, 0))));
+    assert_eq!(won(&[(0, 0), (2, 0), (1, 0), (2, 1), (0, 1), (2, 2)]), GameStatus::Won(Player::O, line((2, 0), (0, 1))));
+    assert_eq!(won(&[(2, 0), (0, 0), (1, 1), (1, 0), (0, 2)]), GameStatus::Won(Player::X, line((2, 0), (-1, 1))));
+    // Completing a row and a diagonal at once reports the row, as a scan
+    // of the board would.
+    let status = won(&[(0, 0), (0, 1), (1, 1), (0, 2), (2, 0), (1, 2), (1, 0), (2, 1), (2, 2)]);
+    assert_eq!(status, GameStatus::Won(Player::X, line((0, 0), (1, 0))));
+    assert_eq!(line((0, 0), (1, 1)).cells(), vec![(0, 0, 0), (1, 1, 0), (2, 2, 0)]);
+}
+#[test]
+fn mnk_games_for_up_to_four_players() {
+    let mut rng = Rng::new(2);
+    for count in 2..=Player::MAX {
+        for &(columns, rows, win_length) in &[(3, 3, 3), (5, 4, 3), (4, 7, 3), (6, 6, 4), (7, 3, 4), (5, 5, 2)] {
+            for _ in 0..100 {
+                let mut game = Tictactoe::mnk(columns, rows, win_length).with_players(Players::new(count));
+                let mut moves = 0;
+                while !game.legal_moves().is_empty() {
+                    let legal = game.legal_moves();
+                    let player = game.current_player();
+                    assert_eq!(player, Player::new(moves % count));
+                    let before = game.get_status();
+                    let status = game.play(player, &legal[rng.below(legal.len())]).unwrap();
+                    moves += 1;
+                    assert_eq!(status, game.scan_status(), "\n{}", game);
+                    // The first win goes to whoever just moved.
+                    if before == GameStatus::InProgress {
+                        if let GameStatus::Won(winner, line) = status {
+                            assert_eq!(winner, player);
+                            assert!(line.length >= win_length);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+#[test]
+fn third_and_fourth_players_win() {
+    let players = Players::from_marks("XOYZ").unwrap().in_order("ZYXO").unwrap();
+    let mut game = Tictactoe::mnk(5, 4, 3).with_players(players);
+    // Z, Y, X and O in turn, until Z fills the top row.
+    let moves = [(0, 0), (4, 0), (0, 3), (1, 1), (1, 0), (4, 1), (0, 2), (2, 2), (2, 0)];
+    let mut status = GameStatus::InProgress;
+    for &(column, row) in &moves {
+        let player = game.current_player();
+        status = game.play(player, &[column, row]).unwrap();
+    }
+    assert_eq!(status, GameStatus::Won(Player::new(3), Line::flat((0, 0), (1, 0), 3)));
+    assert_eq!(game.to_string(), "[Z] [Z] [Z] [ ] [Y] \n[ ] [O] [ ] [ ] [Y] \n[X] [ ] [O] [ ] [ ] \n[X] [ ] [ ] [ ] [ ] \n");
+
+    let mut game = Tictactoe::mnk(3, 3, 3).with_players(Players::new(3));
+    for &(column, row) in &[(0, 0), (1, 0), (0, 2), (2, 0), (0, 1), (1, 2), (1, 1), (2, 1)] {
+        let player = game.current_player();
+        game.play(player, &[column, row]).unwrap();
+    }
+    let status = game.play(Player::new(2), &[2, 2]).unwrap();
+    assert_eq!(status, GameStatus::Won(Player::new(2), Line::flat((0, 2), (1, 0), 3)));
+}
+#[test]
+fn lines_wrap_round_the_edges() {
+    let mut rng = Rng::new(3);
+    for &wrap in &[Wrap::Cylinder, Wrap::Torus] {
+        for &(columns, rows, win_length) in &[(3, 3, 3), (4, 4, 4), (5, 4, 3), (3, 5, 4), (4, 3, 5)] {
+            for _ in 0..50 {
+                let mut game = Tictactoe::mnk(columns, rows, win_length).with_wrap(wrap);
+                while !game.legal_moves().is_empty() {
+                    let moves = game.legal_moves();
+                    let player = game.current_player();
+                    let status = game.play(player, &moves[rng.below(moves.len())]).unwrap();
+                    assert_eq!(status, game.scan_status(), "\n{}", game);
+                }
+            }
+        }
+    }
+
+    // A broken diagonal wins on a torus, but not on a flat board.
+    let moves = [(2, 0), (0, 0), (0, 1), (1, 1), (1, 2)];
+    for &(wrap, won) in &[(Wrap::Flat, false), (Wrap::Torus, true)] {
+        let mut game = Tictactoe::new(Board::new(3)).with_wrap(wrap);
+        let mut status = GameStatus::InProgress;
+        for &(column, row) in &moves {
+            let player = game.current_player();
+            status = game.play(player, &[column, row]).unwrap();
+        }
+        let line = Line::flat((2, 0), (1, 1), 3).wrapped(3, 3);
+        assert_eq!(status == GameStatus::Won(Player::X, line), won);
+    }
+}
+#[test]
+fn moves_are_written_like_chess() {
+    let game = Tictactoe::new(Board::new(3));
+    assert_eq!(game.notation(&[0, 0]), "a1");
+    assert_eq!(game.notation(&[2, 1]), "c2");
+    let wide = Tictactoe::mnk(30, 12, 5);
+    assert_eq!(wide.notation(&[27, 11]), "ab12");
+}
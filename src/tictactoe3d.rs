@@ -0,0 +1,248 @@
+use std::fmt;
+
+use crate::board::{Cell, Player, Players, Square};
+use crate::cube::{Cube, DIRECTIONS_3D};
+
+use crate::game::{
+    Game,
+    GameStatus,
+    GameError,
+};
+
+// Tic-tac-toe in three dimensions: first to get `win_length` in a row along
+// any of the thirteen directions through the cube wins. On 4x4x4, four in a
+// row, it's Qubic. With gravity, moves name a column and row and the stone
+// drops to the lowest free layer, as in 3D connect four.
+#[derive(Clone)]
+pub struct Tictactoe3d {
+    cube: Cube,
+    win_length: usize,
+    gravity: bool,
+    players: Players,
+    stones: usize,
+    // Kept up to date by `play`, which only has to look at the lines
+    // through the square just played.
+    status: GameStatus,
+}
+
+impl Tictactoe3d {
+    pub fn new(columns: usize, rows: usize, layers: usize, win_length: usize) -> Tictactoe3d {
+        Tictactoe3d {
+            cube: Cube::new_cuboid(columns, rows, layers),
+            win_length,
+            gravity: false,
+            players: Players::default(),
+            stones: 0,
+            status: GameStatus::InProgress,
+        }
+    }
+
+    pub fn with_gravity(mut self) -> Tictactoe3d {
+        self.gravity = true;
+        self
+    }
+
+    pub fn with_players(mut self, players: Players) -> Tictactoe3d {
+        self.players = players;
+        self
+    }
+
+    pub fn cube(&self) -> &Cube {
+        &self.cube
+    }
+
+    pub fn win_length(&self) -> usize {
+        self.win_length
+    }
+
+    // The square a move would take, as (column, row, layer).
+    fn target(&self, input: &[usize]) -> Result<(usize, usize, usize), GameError> {
+        let (column, row) = (input[0], input[1]);
+        if column >= self.cube.columns() || row >= self.cube.rows() {
+            return Err(GameError::OutOfBounds)
+        }
+        if self.gravity {
+            return self.cube.pillar_iterator(column, row)
+                .position(|square| square.is_empty())
+                .map(|layer| (column, row, layer))
+                .ok_or(GameError::SquareNotEmpty)
+        }
+        match self.cube.get_square(column, row, input[2]) {
+            Some(Square::Empty) => Ok((column, row, input[2])),
+            Some(Square::Played(_)) => Err(GameError::SquareNotEmpty),
+            None => Err(GameError::OutOfBounds),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.stones == self.cube.columns() * self.cube.rows() * self.cube.layers()
+    }
+
+    fn scan_status(&self) -> GameStatus {
+        let won = self.cube.find_line(self.win_length).and_then(|line| {
+            let (column, row, layer) = line.start;
+            self.cube.get_square(column, row, layer)?.to_option().map(|player| (player, line))
+        });
+        if let Some((player, line)) = won {
+            GameStatus::Won(player, line)
+        } else if self.is_full() {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    // The status once `player` has taken (column, row, layer). A new line
+    // has to run through that square, and the scan comes to directions in
+    // the order of DIRECTIONS_3D, so the first long enough is the one it
+    // would find. Once someone has won, the scan decides who.
+    fn status_after(&self, (column, row, layer): (usize, usize, usize), player: Player) -> GameStatus {
+        if self.status != GameStatus::InProgress {
+            return self.scan_status()
+        }
+        let won = DIRECTIONS_3D.iter()
+            .map(|&step| self.cube.run_through(column, row, layer, step))
+            .find(|line| line.length >= self.win_length);
+        if let Some(line) = won {
+            GameStatus::Won(player, line)
+        } else if self.is_full() {
+            GameStatus::Drawn
+        } else {
+            GameStatus::InProgress
+        }
+    }
+}
+
+impl Game for Tictactoe3d {
+
+    fn get_status(&self) -> GameStatus {
+        self.status.clone()
+    }
+
+    fn play(&mut self, player: Player, input: &[usize]) -> Result<GameStatus, GameError> {
+        let (column, row, layer) = self.target(input)?;
+        self.cube.set_square(column, row, layer, Square::Played(player));
+        self.stones += 1;
+        self.status = self.status_after((column, row, layer), player);
+        Ok(self.status.clone())
+    }
+
+    fn reset(&mut self) {
+        self.cube.reset();
+        self.stones = 0;
+        self.status = GameStatus::InProgress;
+    }
+
+    fn num_inputs(&self) -> usize {
+        if self.gravity { 2 } else { 3 }
+    }
+
+    // Labels go on the square each move would take.
+    fn annotate(&self, labels: Vec<(Vec<usize>, String)>) -> String {
+        let cells = labels.into_iter()
+            .filter_map(|(input, label)| self.target(&input).ok().map(|cell| (cell, label)))
+            .collect();
+        self.cube.annotated(cells).with_marks(self.players.square_marks()).to_string()
+    }
+
+    fn legal_moves(&self) -> Vec<Vec<usize>> {
+        let mut moves = Vec::new();
+        for column in 0..self.cube.columns() {
+            for row in 0..self.cube.rows() {
+                if self.gravity {
+                    if self.target(&[column, row]).is_ok() {
+                        moves.push(vec![column, row]);
+                    }
+                    continue
+                }
+                for layer in 0..self.cube.layers() {
+                    if self.cube.get_square(column, row, layer) == Some(Square::Empty) {
+                        moves.push(vec![column, row, layer]);
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    fn current_player(&self) -> Player {
+        self.players.to_move(self.stones)
+    }
+
+    fn players(&self) -> &Players {
+        &self.players
+    }
+
+    fn position_hash(&self) -> u64 {
+        self.cube.hash() ^ self.cube.side_to_move(self.current_player())
+    }
+}
+
+impl fmt::Display for Tictactoe3d {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.cube.annotated(Vec::new()).with_marks(self.players.square_marks()))
+    }
+}
+
+#[cfg(test)]
+use crate::board::Line;
+#[cfg(test)]
+use crate::rng::Rng;
+
+#[test]
+fn qubic_is_won_through_the_middle() {
+    let mut game = Tictactoe3d::new(4, 4, 4, 4);
+    assert_eq!(game.num_inputs(), 3);
+    assert_eq!(game.legal_moves().len(), 64);
+    let moves = [[0, 0, 0], [0, 1, 0], [1, 1, 1], [0, 2, 0], [2, 2, 2], [0, 3, 0], [3, 3, 3]];
+    let mut status = GameStatus::InProgress;
+    for input in &moves {
+        let player = game.current_player();
+        status = game.play(player, input).unwrap();
+    }
+    assert_eq!(status, GameStatus::Won(Player::X, Line::layered((0, 0, 0), (1, 1, 1), 4)));
+    assert_eq!(status, game.scan_status());
+    assert_eq!(game.play(Player::O, &[1, 1, 1]), Err(GameError::SquareNotEmpty));
+    assert_eq!(game.play(Player::O, &[1, 1, 4]), Err(GameError::OutOfBounds));
+    assert_eq!(game.play(Player::O, &[4, 1, 1]), Err(GameError::OutOfBounds));
+}
+#[test]
+fn stones_drop_with_gravity() {
+    let mut game = Tictactoe3d::new(2, 2, 3, 3).with_gravity();
+    assert_eq!(game.num_inputs(), 2);
+    assert_eq!(game.legal_moves().len(), 4);
+    for _ in 0..3 {
+        let player = game.current_player();
+        game.play(player, &[1, 0]).unwrap();
+    }
+    assert_eq!(game.play(Player::O, &[1, 0]), Err(GameError::SquareNotEmpty));
+    assert_eq!(game.play(Player::O, &[2, 0]), Err(GameError::OutOfBounds));
+    assert_eq!(game.legal_moves(), vec![vec![0, 0], vec![0, 1], vec![1, 1]]);
+    assert_eq!(game.to_string(),
+        "Layer 0\n[ ] [X] \n[ ] [ ] \nLayer 1\n[ ] [O] \n[ ] [ ] \nLayer 2\n[ ] [X] \n[ ] [ ] \n");
+    assert_eq!(game.annotate(vec![(vec![1, 1], "a".to_string())]),
+        "Layer 0\n[ ] [X] \n[ ] [a] \nLayer 1\n[ ] [O] \n[ ] [ ] \nLayer 2\n[ ] [X] \n[ ] [ ] \n");
+}
+#[test]
+fn incremental_status_matches_the_scan_in_three_dimensions() {
+    let mut rng = Rng::new(4);
+    for count in 2..=3 {
+        for &(columns, rows, layers, win_length, gravity) in &[(3, 3, 3, 3, false), (4, 4, 4, 4, false), (4, 3, 2, 2, false), (5, 4, 4, 4, true)] {
+            for _ in 0..50 {
+                let mut game = Tictactoe3d::new(columns, rows, layers, win_length).with_players(Players::new(count));
+                if gravity {
+                    game = game.with_gravity();
+                }
+                while !game.legal_moves().is_empty() {
+                    let moves = game.legal_moves();
+                    let player = game.current_player();
+                    let hash = game.position_hash();
+                    let status = game.play(player, &moves[rng.below(moves.len())]).unwrap();
+                    assert_eq!(status, game.scan_status(), "\n{}", game);
+                    assert_ne!(game.position_hash(), hash);
+                }
+                assert_ne!(game.get_status(), GameStatus::InProgress);
+            }
+        }
+    }
+}
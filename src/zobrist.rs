@@ -0,0 +1,139 @@
+use std::fmt;
+
+use crate::board::{Cell, Player, Square};
+use crate::rng::Rng;
+
+// Seed for the keys boards use unless told otherwise. Hashes stored on disk
+// are only good for the seed they were made with.
+pub const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+// Random keys for Zobrist hashing: one per square and kind of piece, and one for
+// the side to move. A position's hash is the xor of the keys for the marks
+// on it, so a move changes it with a single xor. Keys are drawn in a fixed
+// order from a seeded generator, so the same seed always gives the same
+// hashes.
+pub struct Zobrist {
+    seed: u64,
+    rows: usize,
+    kinds: usize,
+    squares: Vec<u64>,
+    // Nothing for X, so a two-player hash only changes when O is to move.
+    sides: [u64; Player::MAX],
+}
+
+impl Zobrist {
+    // Keys for the first two kinds, X and O on a board of Squares, are drawn
+    // first, so that hashes of two-player games don't depend on how many
+    // kinds there could be.
+    pub fn new(columns: usize, rows: usize, kinds: usize, seed: u64) -> Zobrist {
+        let mut rng = Rng::new(seed);
+        let mut squares = vec![0; columns * rows * kinds];
+        for square in 0..columns * rows {
+            for kind in 0..kinds.min(2) {
+                squares[square * kinds + kind] = rng.next_u64();
+            }
+        }
+        let mut sides = [0; Player::MAX];
+        sides[1] = rng.next_u64();
+        for square in 0..columns * rows {
+            for kind in 2..kinds {
+                squares[square * kinds + kind] = rng.next_u64();
+            }
+        }
+        for side in &mut sides[2..] {
+            *side = rng.next_u64();
+        }
+        Zobrist { seed, rows, kinds, squares, sides }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn piece(&self, column: usize, row: usize, kind: usize) -> u64 {
+        debug_assert!(kind < self.kinds, "No keys for a piece of kind {}", kind);
+        self.squares[(column * self.rows + row) * self.kinds + kind]
+    }
+
+    // The key for `player`'s mark on a board of Squares.
+    pub fn square(&self, column: usize, row: usize, player: Player) -> u64 {
+        self.piece(column, row, Square::Played(player).kind().unwrap())
+    }
+
+    // Xored into a position's hash to tell whose move it is.
+    pub fn side_to_move(&self, player: Player) -> u64 {
+        self.sides[player.index()]
+    }
+}
+
+impl fmt::Debug for Zobrist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Zobrist {{ seed: {:#x} }}", self.seed)
+    }
+}
+
+#[cfg(test)]
+use crate::board::Board;
+#[cfg(test)]
+use crate::connectn::ConnectN;
+#[cfg(test)]
+use crate::game::Game;
+#[cfg(test)]
+use crate::tictactoe::Tictactoe;
+
+#[test]
+fn hashes_follow_the_marks_on_the_board() {
+    let mut board = Board::new_rectangle(4, 3);
+    assert_eq!(board.hash(), 0);
+    board.set_square(1, 2, Square::Played(Player::X));
+    board.set_square(3, 0, Square::Played(Player::O));
+    let both = board.hash();
+    assert_eq!(both, board.zobrist().square(1, 2, Player::X) ^ board.zobrist().square(3, 0, Player::O));
+    // Recomputing from scratch with the same keys agrees.
+    assert_eq!(board.clone().with_seed(DEFAULT_SEED).hash(), both);
+    // Overwriting a mark takes the old one out.
+    board.set_square(1, 2, Square::Played(Player::O));
+    board.set_square(1, 2, Square::Played(Player::X));
+    assert_eq!(board.hash(), both);
+    board.set_square(1, 2, Square::Empty);
+    assert_eq!(board.hash(), board.zobrist().square(3, 0, Player::O));
+    board.reset();
+    assert_eq!(board.hash(), 0);
+}
+#[test]
+fn hashes_depend_on_the_seed_only() {
+    let mut board = Board::new(3);
+    board.set_square(0, 0, Square::Played(Player::X));
+    let reseeded = board.clone().with_seed(7);
+    assert_ne!(reseeded.hash(), board.hash());
+    assert_eq!(reseeded.zobrist().seed(), 7);
+    assert_eq!(Board::new(3).with_seed(7).zobrist().square(0, 0, Player::X), reseeded.hash());
+    // Keys are part of the format of anything stored on disk.
+    assert_eq!(board.hash(), 0xc0e1_6b16_3a85_a4dc);
+}
+#[test]
+fn transpositions_hash_the_same() {
+    let play = |moves: &[usize]| {
+        let mut game = ConnectN::new(7, 6, 4);
+        for &column in moves {
+            let player = game.current_player();
+            game.play(player, &[column]).unwrap();
+        }
+        game.position_hash()
+    };
+    assert_eq!(play(&[3, 2, 4, 2]), play(&[4, 2, 3, 2]));
+    assert_ne!(play(&[3, 2, 4, 2]), play(&[3, 4, 2, 2]));
+    // The same marks with the other side to move are another position.
+    let mut game = Tictactoe::new(Board::new(3));
+    game.play(Player::X, &[1, 1]).unwrap();
+    let mut passed = Tictactoe::new(Board::new(3));
+    passed.play(Player::X, &[1, 1]).unwrap();
+    passed.play(Player::O, &[0, 0]).unwrap();
+    passed.play(Player::X, &[2, 2]).unwrap();
+    assert_eq!(game.board().hash() ^ passed.board().hash(),
+        game.board().zobrist().square(0, 0, Player::O) ^ game.board().zobrist().square(2, 2, Player::X));
+    assert_ne!(game.position_hash(), game.board().hash());
+    let mut reset = passed.clone();
+    reset.reset();
+    assert_eq!(reset.position_hash(), Tictactoe::new(Board::new(3)).position_hash());
+}